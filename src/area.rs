@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use gtfs_structures::{Stop, StopTime};
+use serde_json::Value;
+
+/// A (longitude, latitude) pair, in the same order as GeoJSON coordinates
+type Point = (f64, f64);
+
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    exterior: Vec<Point>,
+    holes: Vec<Vec<Point>>,
+}
+
+impl Polygon {
+    fn contains(&self, point: Point) -> bool {
+        ring_contains(&self.exterior, point) && !self.holes.iter().any(|e| ring_contains(e, point))
+    }
+}
+
+/// Ray casting: counts how many edges of the ring a horizontal ray from the point crosses
+fn ring_contains(ring: &[Point], (x, y): Point) -> bool {
+    let mut inside = false;
+    for (i, &(xi, yi)) in ring.iter().enumerate() {
+        let (xj, yj) = ring[(i + ring.len() - 1) % ring.len()];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Geographic area in which stops must lie
+#[derive(Debug, Clone)]
+pub enum Area {
+    BoundingBox {
+        min_lon: f64,
+        min_lat: f64,
+        max_lon: f64,
+        max_lat: f64,
+    },
+    Polygons(Vec<Polygon>),
+}
+
+impl Area {
+    /// Reads the polygons of a GeoJSON file. Polygons and multi-polygons are
+    /// accepted, as plain geometries, features or feature collections.
+    pub fn from_geojson(path: &str) -> Result<Area, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&content).map_err(|e| format!("{}: {}", path, e))?;
+        let mut polygons = Vec::new();
+        collect_polygons(&json, &mut polygons)?;
+        if polygons.is_empty() {
+            return Err(format!("{}: no polygon found", path));
+        }
+        Ok(Area::Polygons(polygons))
+    }
+
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        match self {
+            Area::BoundingBox {
+                min_lon,
+                min_lat,
+                max_lon,
+                max_lat,
+            } => (*min_lon..=*max_lon).contains(&lon) && (*min_lat..=*max_lat).contains(&lat),
+            Area::Polygons(polygons) => polygons.iter().any(|e| e.contains((lon, lat))),
+        }
+    }
+}
+
+impl FromStr for Area {
+    type Err = String;
+
+    /// Parses a bounding box given as `min_lon,min_lat,max_lon,max_lat`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|e| e.trim().parse::<f64>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+            return Err("expected min_lon,min_lat,max_lon,max_lat".to_string());
+        };
+        Ok(Area::BoundingBox {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        })
+    }
+}
+
+fn parse_ring(value: &Value) -> Result<Vec<Point>, String> {
+    value
+        .as_array()
+        .ok_or("a ring must be an array of positions")?
+        .iter()
+        .map(|position| match position.as_array().map(|e| &e[..]) {
+            Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+                (Some(lon), Some(lat)) => Ok((lon, lat)),
+                _ => Err("a position must contain numbers".to_string()),
+            },
+            _ => Err("a position must be [longitude, latitude]".to_string()),
+        })
+        .collect()
+}
+
+fn parse_polygon(value: &Value) -> Result<Polygon, String> {
+    let mut rings = value
+        .as_array()
+        .ok_or("a polygon must be an array of rings")?
+        .iter()
+        .map(parse_ring)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter();
+    let exterior = rings.next().ok_or("a polygon must have an exterior ring")?;
+    Ok(Polygon {
+        exterior,
+        holes: rings.collect(),
+    })
+}
+
+fn collect_polygons(json: &Value, polygons: &mut Vec<Polygon>) -> Result<(), String> {
+    match json.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in json["features"].as_array().into_iter().flatten() {
+                collect_polygons(feature, polygons)?;
+            }
+        }
+        Some("Feature") => collect_polygons(&json["geometry"], polygons)?,
+        Some("GeometryCollection") => {
+            for geometry in json["geometries"].as_array().into_iter().flatten() {
+                collect_polygons(geometry, polygons)?;
+            }
+        }
+        Some("Polygon") => polygons.push(parse_polygon(&json["coordinates"])?),
+        Some("MultiPolygon") => {
+            for polygon in json["coordinates"].as_array().into_iter().flatten() {
+                polygons.push(parse_polygon(polygon)?);
+            }
+        }
+        // Points and lines cannot contain stops
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Whether the stop lies in all the areas. Stops without coordinates are never inside.
+pub fn stop_inside(areas: &[Area], stop: &Stop) -> bool {
+    match (stop.longitude, stop.latitude) {
+        (Some(lon), Some(lat)) => areas.iter().all(|e| e.contains(lon, lat)),
+        _ => false,
+    }
+}
+
+/// The longest run of consecutive stops inside the areas
+pub fn longest_run_inside<'a>(stop_times: &'a [StopTime], areas: &[Area]) -> &'a [StopTime] {
+    let mut best = 0..0;
+    let mut start = 0;
+    for (idx, stop_time) in stop_times.iter().enumerate() {
+        if !stop_inside(areas, &stop_time.stop) {
+            start = idx + 1;
+        } else if idx + 1 - start > best.len() {
+            best = start..idx + 1;
+        }
+    }
+    &stop_times[best]
+}
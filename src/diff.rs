@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::{GitRoute, GitStop, RouteId, RouteName, StopId, StopName};
+
+/// A stop whose name changed, its ID did not
+#[derive(Debug, Clone, Serialize)]
+pub struct RenamedStop {
+    pub from: StopName,
+    pub to: StopName,
+}
+
+/// Changes of a route present in both networks
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteDiff {
+    pub name: RouteName,
+    pub added_stops: Vec<StopName>,
+    pub removed_stops: Vec<StopName>,
+    pub renamed_stops: Vec<RenamedStop>,
+    // Stops served by both versions, but not in the same order relative to the others
+    pub reordered_stops: Vec<StopName>,
+}
+
+/// Routes that started or stopped serving a stop shared by several routes
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedStopDiff {
+    pub stop: StopName,
+    pub added_routes: Vec<RouteName>,
+    pub removed_routes: Vec<RouteName>,
+}
+
+/// Differences between two versions of the selected routes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NetworkDiff {
+    pub added_routes: Vec<RouteName>,
+    pub removed_routes: Vec<RouteName>,
+    pub changed_routes: Vec<RouteDiff>,
+    pub added_stops: Vec<StopName>,
+    pub removed_stops: Vec<StopName>,
+    pub shared_stops: Vec<SharedStopDiff>,
+}
+
+/// Every stop of the network, by ID
+fn network_stops(routes: &HashMap<RouteId, GitRoute>) -> BTreeMap<&StopId, &StopName> {
+    routes
+        .values()
+        .flat_map(|route| route.stops().iter().map(|e| (&e.id, &e.name)))
+        .collect()
+}
+
+/// Names of the routes serving each stop
+fn routes_by_stop(routes: &HashMap<RouteId, GitRoute>) -> BTreeMap<&StopId, BTreeSet<&RouteName>> {
+    let mut by_stop: BTreeMap<&StopId, BTreeSet<&RouteName>> = BTreeMap::new();
+    for route in routes.values() {
+        for stop in route.stops() {
+            by_stop.entry(&stop.id).or_default().insert(&route.name);
+        }
+    }
+    by_stop
+}
+
+/// Stops of `new` served by both versions that are not part of their longest common
+/// subsequence, i.e. that moved relative to the other stops
+fn reordered_stops(old: &[&GitStop], new: &[&GitStop]) -> Vec<StopName> {
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i].id == new[j].id {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut in_order = BTreeSet::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i].id == new[j].id {
+            in_order.insert(j);
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    new.iter()
+        .enumerate()
+        .filter(|(idx, _)| !in_order.contains(idx))
+        .map(|(_, e)| e.name.clone())
+        .collect()
+}
+
+fn diff_route(old: &GitRoute, new: &GitRoute) -> RouteDiff {
+    let old_ids = old.stops().iter().map(|e| &e.id).collect::<BTreeSet<_>>();
+    let new_ids = new.stops().iter().map(|e| &e.id).collect::<BTreeSet<_>>();
+
+    let common_old = old
+        .stops()
+        .iter()
+        .filter(|e| new_ids.contains(&e.id))
+        .collect::<Vec<_>>();
+    let common_new = new
+        .stops()
+        .iter()
+        .filter(|e| old_ids.contains(&e.id))
+        .collect::<Vec<_>>();
+
+    let mut renamed_stops = Vec::new();
+    for stop in &common_new {
+        let old_stop = common_old.iter().find(|e| e.id == stop.id).unwrap();
+        if old_stop.name != stop.name
+            && !renamed_stops
+                .iter()
+                .any(|e: &RenamedStop| e.to == stop.name)
+        {
+            renamed_stops.push(RenamedStop {
+                from: old_stop.name.clone(),
+                to: stop.name.clone(),
+            });
+        }
+    }
+
+    RouteDiff {
+        name: new.name.clone(),
+        added_stops: new
+            .stops()
+            .iter()
+            .filter(|e| !old_ids.contains(&e.id))
+            .map(|e| e.name.clone())
+            .collect(),
+        removed_stops: old
+            .stops()
+            .iter()
+            .filter(|e| !new_ids.contains(&e.id))
+            .map(|e| e.name.clone())
+            .collect(),
+        renamed_stops,
+        reordered_stops: reordered_stops(&common_old, &common_new),
+    }
+}
+
+pub fn diff_networks(
+    old: &HashMap<RouteId, GitRoute>,
+    new: &HashMap<RouteId, GitRoute>,
+) -> NetworkDiff {
+    let mut diff = NetworkDiff::default();
+
+    let ids = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    for id in ids {
+        match (old.get(id), new.get(id)) {
+            (None, Some(new)) => diff.added_routes.push(new.name.clone()),
+            (Some(old), None) => diff.removed_routes.push(old.name.clone()),
+            (Some(old), Some(new)) => {
+                let route = diff_route(old, new);
+                if !route.is_empty() {
+                    diff.changed_routes.push(route);
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    let old_stops = network_stops(old);
+    let new_stops = network_stops(new);
+    diff.added_stops = new_stops
+        .iter()
+        .filter(|(id, _)| !old_stops.contains_key(*id))
+        .map(|(_, name)| name.to_string())
+        .collect();
+    diff.removed_stops = old_stops
+        .iter()
+        .filter(|(id, _)| !new_stops.contains_key(*id))
+        .map(|(_, name)| name.to_string())
+        .collect();
+
+    let old_routes = routes_by_stop(old);
+    let new_routes = routes_by_stop(new);
+    let empty = BTreeSet::new();
+    // Stops are visited in both networks, and distinct stops can have the same name
+    let mut visited = BTreeSet::new();
+    for (id, name) in new_stops.iter().chain(old_stops.iter()) {
+        let before = old_routes.get(id).unwrap_or(&empty);
+        let after = new_routes.get(id).unwrap_or(&empty);
+        let shared = before.len() > 1 || after.len() > 1;
+        if !visited.insert(*id) || !shared || before == after {
+            continue;
+        }
+        diff.shared_stops.push(SharedStopDiff {
+            stop: name.to_string(),
+            added_routes: after.difference(before).map(|e| e.to_string()).collect(),
+            removed_routes: before.difference(after).map(|e| e.to_string()).collect(),
+        });
+    }
+
+    diff
+}
+
+impl RouteDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_stops.is_empty()
+            && self.removed_stops.is_empty()
+            && self.renamed_stops.is_empty()
+            && self.reordered_stops.is_empty()
+    }
+}
+
+impl NetworkDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_routes.is_empty()
+            && self.removed_routes.is_empty()
+            && self.changed_routes.is_empty()
+            && self.added_stops.is_empty()
+            && self.removed_stops.is_empty()
+            && self.shared_stops.is_empty()
+    }
+
+    /// One line per change, e.g. `M2: +Ouchy, -Flon, Gare -> Lausanne Gare, ~Délices`
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "No changes".to_string();
+        }
+
+        let mut lines = Vec::new();
+        if !self.added_routes.is_empty() {
+            lines.push(format!("Added routes: {}", self.added_routes.join(", ")));
+        }
+        if !self.removed_routes.is_empty() {
+            lines.push(format!(
+                "Removed routes: {}",
+                self.removed_routes.join(", ")
+            ));
+        }
+        if !self.added_stops.is_empty() {
+            lines.push(format!("Added stops: {}", self.added_stops.join(", ")));
+        }
+        if !self.removed_stops.is_empty() {
+            lines.push(format!("Removed stops: {}", self.removed_stops.join(", ")));
+        }
+        for route in &self.changed_routes {
+            let changes = route
+                .added_stops
+                .iter()
+                .map(|e| format!("+{}", e))
+                .chain(route.removed_stops.iter().map(|e| format!("-{}", e)))
+                .chain(
+                    route
+                        .renamed_stops
+                        .iter()
+                        .map(|e| format!("{} -> {}", e.from, e.to)),
+                )
+                .chain(route.reordered_stops.iter().map(|e| format!("~{}", e)))
+                .collect::<Vec<_>>();
+            lines.push(format!("{}: {}", route.name, changes.join(", ")));
+        }
+        for stop in &self.shared_stops {
+            let changes = stop
+                .added_routes
+                .iter()
+                .map(|e| format!("+{}", e))
+                .chain(stop.removed_routes.iter().map(|e| format!("-{}", e)))
+                .collect::<Vec<_>>();
+            lines.push(format!("Shared stop {}: {}", stop.stop, changes.join(", ")));
+        }
+        lines.join("\n")
+    }
+}
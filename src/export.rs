@@ -0,0 +1,101 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::{get_conflicts, CommitIdx, GitRoute, GitStop, Plan, RouteId, RouteName, StopId};
+
+/// Version of the format described in `docs/export.schema.json`, bumped on breaking changes
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct ExportRoute<'a> {
+    id: &'a RouteId,
+    name: &'a RouteName,
+    direction_id: Option<u8>,
+    headsign: Option<&'a String>,
+    opposite_headsign: Option<&'a String>,
+    reversed: bool,
+    stops: &'a [GitStop],
+}
+
+#[derive(Serialize)]
+struct ExportSharedStop<'a> {
+    id: &'a StopId,
+    name: &'a str,
+    routes: Vec<&'a RouteId>,
+}
+
+#[derive(Serialize)]
+struct ExportCommit<'a> {
+    id: CommitIdx,
+    message: &'a str,
+    stop: &'a StopId,
+    route: &'a RouteId,
+    parents: &'a [CommitIdx],
+}
+
+#[derive(Serialize)]
+struct Export<'a> {
+    version: u32,
+    routes: Vec<ExportRoute<'a>>,
+    shared_stops: Vec<ExportSharedStop<'a>>,
+    commits: Vec<ExportCommit<'a>>,
+    heads: BTreeMap<&'a RouteId, CommitIdx>,
+}
+
+/// The routes, in their final order, and the commits planned for them, as JSON
+pub fn export_json(routes: &HashMap<RouteId, GitRoute>, plan: &Plan) -> String {
+    let mut export_routes = routes
+        .values()
+        .map(|route| ExportRoute {
+            id: &route.id,
+            name: &route.name,
+            direction_id: route.direction.map(|e| e.id()),
+            headsign: route.headsign.as_ref(),
+            opposite_headsign: route.opposite_headsign.as_ref(),
+            reversed: route.reversed,
+            stops: route.stops(),
+        })
+        .collect::<Vec<_>>();
+    export_routes.sort_by_key(|e| e.id);
+
+    let conflicts = get_conflicts(routes);
+    let mut shared_stops = conflicts
+        .iter()
+        .map(|(id, shared)| {
+            let route = routes.get(shared.first().unwrap()).unwrap();
+            let stop = route.stops().iter().find(|e| &e.id == id).unwrap();
+            let mut shared = shared.iter().collect::<Vec<_>>();
+            shared.sort();
+            shared.dedup();
+            ExportSharedStop {
+                id,
+                name: &stop.name,
+                routes: shared,
+            }
+        })
+        .collect::<Vec<_>>();
+    shared_stops.sort_by_key(|e| e.id);
+
+    let commits = plan
+        .commits
+        .iter()
+        .enumerate()
+        .map(|(id, commit)| ExportCommit {
+            id,
+            message: &commit.message,
+            stop: &commit.stop,
+            route: &commit.route,
+            parents: &commit.parents,
+        })
+        .collect();
+
+    let export = Export {
+        version: EXPORT_VERSION,
+        routes: export_routes,
+        shared_stops,
+        commits,
+        heads: plan.heads.iter().map(|(id, head)| (id, *head)).collect(),
+    };
+    serde_json::to_string_pretty(&export).unwrap()
+}
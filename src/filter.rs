@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use clap::ValueEnum;
+use gtfs_structures::{Agency, Route, RouteType};
+use inquire::{list_option::ListOption, validator::Validation, MultiSelect};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Kind of a route, from its GTFS `route_type`. Extended route types (HVT) are
+/// matched by the base type they belong to, e.g. 109 (suburban railway) is `rail`.
+#[derive(
+    ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum RouteKind {
+    Tram,
+    Subway,
+    Rail,
+    Bus,
+    Ferry,
+    CableCar,
+    Gondola,
+    Funicular,
+    Coach,
+    Air,
+    Taxi,
+    Other,
+}
+
+impl From<RouteType> for RouteKind {
+    fn from(route_type: RouteType) -> Self {
+        match route_type {
+            RouteType::Tramway => RouteKind::Tram,
+            RouteType::Subway => RouteKind::Subway,
+            RouteType::Rail => RouteKind::Rail,
+            RouteType::Bus => RouteKind::Bus,
+            RouteType::Ferry => RouteKind::Ferry,
+            RouteType::CableCar => RouteKind::CableCar,
+            RouteType::Gondola => RouteKind::Gondola,
+            RouteType::Funicular => RouteKind::Funicular,
+            RouteType::Coach => RouteKind::Coach,
+            RouteType::Air => RouteKind::Air,
+            RouteType::Taxi => RouteKind::Taxi,
+            RouteType::Other(_) => RouteKind::Other,
+        }
+    }
+}
+
+impl RouteKind {
+    /// Heavier modes host the shared stops, lighter ones join them
+    pub fn host_rank(self) -> u8 {
+        match self {
+            RouteKind::Rail => 0,
+            RouteKind::Subway => 1,
+            RouteKind::Tram => 2,
+            RouteKind::Funicular => 3,
+            RouteKind::Ferry => 4,
+            RouteKind::CableCar => 5,
+            RouteKind::Gondola => 6,
+            RouteKind::Coach => 7,
+            RouteKind::Bus => 8,
+            RouteKind::Taxi => 9,
+            RouteKind::Air => 10,
+            RouteKind::Other => 11,
+        }
+    }
+}
+
+impl std::fmt::Display for RouteKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = self.to_possible_value().unwrap();
+        write!(f, "{}", name.get_name())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RouteKindDisplayWrapper(RouteKind, usize);
+
+impl std::fmt::Display for RouteKindDisplayWrapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({} routes)", self.0, self.1)
+    }
+}
+
+/// Asks which kinds of routes should be listed. Everything is selected by default.
+pub fn prompt_route_kinds<'a>(routes: impl Iterator<Item = &'a Route>) -> HashSet<RouteKind> {
+    let mut counts: BTreeMap<RouteKind, usize> = BTreeMap::new();
+    for route in routes {
+        *counts.entry(RouteKind::from(route.route_type)).or_default() += 1;
+    }
+
+    if counts.len() <= 1 {
+        return counts.into_keys().collect();
+    }
+
+    let options = counts
+        .into_iter()
+        .map(|(kind, count)| RouteKindDisplayWrapper(kind, count))
+        .collect::<Vec<_>>();
+    let all = (0..options.len()).collect::<Vec<_>>();
+
+    MultiSelect::new("Which kinds of routes do you want to list?", options)
+        .with_default(&all)
+        .with_validator(|a: &[ListOption<&RouteKindDisplayWrapper>]| {
+            if a.is_empty() {
+                return Ok(Validation::Invalid(
+                    "At least one kind of route must be selected".into(),
+                ));
+            }
+            Ok(Validation::Valid)
+        })
+        .prompt()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.0)
+        .collect()
+}
+
+/// Lookup of the agency operating each route
+pub struct Agencies<'a> {
+    by_id: HashMap<&'a str, &'a Agency>,
+    // A route without `agency_id` belongs to the only agency of the feed
+    single: Option<&'a Agency>,
+}
+
+impl<'a> Agencies<'a> {
+    pub fn new(agencies: &'a [Agency]) -> Self {
+        let by_id = agencies
+            .iter()
+            .filter_map(|agency| agency.id.as_deref().map(|id| (id, agency)))
+            .collect();
+        let single = if agencies.len() == 1 {
+            agencies.first()
+        } else {
+            None
+        };
+        Agencies { by_id, single }
+    }
+
+    pub fn of(&self, route: &Route) -> Option<&'a Agency> {
+        match route.agency_id.as_deref() {
+            Some(id) => self.by_id.get(id).copied(),
+            None => self.single,
+        }
+    }
+}
+
+/// Whether the agency is one of the filters, given by `agency_id` or by name (case-insensitive)
+pub fn matches_agency(agency: Option<&Agency>, filters: &[String]) -> bool {
+    let Some(agency) = agency else {
+        return false;
+    };
+    filters.iter().any(|filter| {
+        agency.id.as_deref() == Some(filter.as_str()) || agency.name.eq_ignore_ascii_case(filter)
+    })
+}
+
+/// Checks the syntax of a `--prefilter-regex`, so that a typo is a usage error
+pub fn parse_regex(pattern: &str) -> Result<String, String> {
+    Regex::new(pattern)
+        .map(|_| pattern.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Matches routes by their `route_id`, short name or long name
+pub struct RouteNameFilter {
+    patterns: Vec<Regex>,
+}
+
+impl RouteNameFilter {
+    /// `globs` is a comma-separated list of exact names or glob patterns (`*` and `?`),
+    /// which must match the whole name. `regexes` only have to match a part of it.
+    pub fn new(globs: &str, regexes: &[String], ignore_case: bool) -> Result<Self, regex::Error> {
+        let globs = globs
+            .split(',')
+            .filter(|e| !e.is_empty())
+            .map(glob_to_regex)
+            .collect::<Vec<_>>();
+        let patterns = globs
+            .iter()
+            .chain(regexes)
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(ignore_case)
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RouteNameFilter { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, route: &Route) -> bool {
+        let names = [
+            Some(&route.id),
+            route.short_name.as_ref(),
+            route.long_name.as_ref(),
+        ];
+        names
+            .into_iter()
+            .flatten()
+            .any(|name| self.patterns.iter().any(|e| e.is_match(name)))
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    pattern
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use gtfs_structures::Stop;
+use serde::Serialize;
+
+use crate::overrides::Overrides;
+use crate::{RouteDisplayWrapper, StopId, StopName};
+
+/// Stops with the same name but different IDs, close enough to be the same stop
+#[derive(Debug, Clone, Serialize)]
+pub struct MissedMerge {
+    name: StopName,
+    stops: [StopId; 2],
+    /// Meters between the closest stops of both IDs
+    distance: f64,
+}
+
+/// Stops of the feed that end up as a single stop, but look like different places
+#[derive(Debug, Clone, Serialize)]
+pub struct FalseMerge {
+    id: StopId,
+    /// `(stop_id, stop_name)` as in the feed
+    stops: Vec<(String, StopName)>,
+    /// Meters between the farthest stops, if they all have coordinates
+    distance: Option<f64>,
+}
+
+/// Routes following the same stops, one of them is likely redundant
+#[derive(Debug, Clone, Serialize)]
+pub struct IdenticalPattern {
+    routes: Vec<String>,
+    from: StopName,
+    to: StopName,
+    stops: usize,
+}
+
+/// Stop patterns going twice through the same stop, which the graph cannot draw
+#[derive(Debug, Clone, Serialize)]
+pub struct RepeatedStop {
+    route: String,
+    trip: String,
+    stop: StopName,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LintReport {
+    missed_merges: Vec<MissedMerge>,
+    false_merges: Vec<FalseMerge>,
+    identical_patterns: Vec<IdenticalPattern>,
+    repeated_stops: Vec<RepeatedStop>,
+}
+
+fn stop_name(stop: &Stop) -> StopName {
+    stop.name.clone().unwrap_or_else(|| stop.id.clone())
+}
+
+/// Great-circle distance in meters
+fn distance(a: &Stop, b: &Stop) -> Option<f64> {
+    const EARTH_RADIUS: f64 = 6_371_000.0;
+    let (lon_a, lat_a) = (a.longitude?.to_radians(), a.latitude?.to_radians());
+    let (lon_b, lat_b) = (b.longitude?.to_radians(), b.latitude?.to_radians());
+    let h = ((lat_b - lat_a) / 2.0).sin().powi(2)
+        + lat_a.cos() * lat_b.cos() * ((lon_b - lon_a) / 2.0).sin().powi(2);
+    Some(2.0 * EARTH_RADIUS * h.sqrt().asin())
+}
+
+/// Looks for likely mistakes of the feed in the stop patterns of the candidate routes. Stops
+/// are identified as in the graph, with the overrides applied. `max_distance` is the distance
+/// in meters under which stops with the same name should be the same stop.
+pub fn lint(
+    routes: &[RouteDisplayWrapper],
+    overrides: &Overrides,
+    max_distance: f64,
+) -> LintReport {
+    let mut report = LintReport::default();
+
+    // Stops of the feed, by ID in the graph
+    let mut stops: BTreeMap<StopId, BTreeMap<String, Arc<Stop>>> = BTreeMap::new();
+    for route in routes {
+        for stop_time in &route.trip.stop_times {
+            stops
+                .entry(overrides.stop_id(&stop_time.stop.id))
+                .or_default()
+                .insert(stop_time.stop.id.clone(), stop_time.stop.clone());
+        }
+    }
+
+    for (id, group) in &stops {
+        let names = group
+            .values()
+            .map(|e| stop_name(e).to_lowercase())
+            .collect::<BTreeSet<_>>();
+        let distances = group
+            .values()
+            .flat_map(|a| group.values().map(move |b| distance(a, b)))
+            .collect::<Option<Vec<_>>>();
+        let farthest = distances.map(|e| e.into_iter().fold(0.0, f64::max));
+        if names.len() > 1 || farthest.is_some_and(|e| e > max_distance) {
+            report.false_merges.push(FalseMerge {
+                id: id.clone(),
+                stops: group
+                    .iter()
+                    .map(|(stop_id, stop)| (stop_id.clone(), stop_name(stop)))
+                    .collect(),
+                distance: farthest.map(f64::round),
+            });
+        }
+    }
+
+    let mut by_name: BTreeMap<String, Vec<&StopId>> = BTreeMap::new();
+    for (id, group) in &stops {
+        for stop in group.values() {
+            by_name
+                .entry(stop_name(stop).trim().to_lowercase())
+                .or_default()
+                .push(id);
+        }
+    }
+    for ids in by_name.values_mut() {
+        ids.dedup();
+        for (idx, a) in ids.iter().enumerate() {
+            for b in &ids[idx + 1..] {
+                let closest = stops[*a]
+                    .values()
+                    .flat_map(|x| stops[*b].values().filter_map(move |y| distance(x, y)))
+                    .fold(f64::INFINITY, f64::min);
+                if closest <= max_distance {
+                    report.missed_merges.push(MissedMerge {
+                        name: stop_name(stops[*a].values().next().unwrap()),
+                        stops: [(*a).clone(), (*b).clone()],
+                        distance: closest.round(),
+                    });
+                }
+            }
+        }
+    }
+
+    let mut patterns: BTreeMap<Vec<StopId>, BTreeSet<String>> = BTreeMap::new();
+    for route in routes {
+        let pattern = route
+            .trip
+            .stop_times
+            .iter()
+            .map(|e| overrides.stop_id(&e.stop.id))
+            .collect::<Vec<_>>();
+
+        // Consecutive platforms of a same stop are a single commit
+        let mut visits = pattern.clone();
+        visits.dedup();
+        let mut seen = BTreeSet::new();
+        let mut repeated = BTreeSet::new();
+        for id in visits {
+            if !seen.insert(id.clone()) && repeated.insert(id.clone()) {
+                report.repeated_stops.push(RepeatedStop {
+                    route: route.route.to_string(),
+                    trip: route.trip.id.clone(),
+                    stop: stop_name(stops[&id].values().next().unwrap()),
+                });
+            }
+        }
+
+        patterns
+            .entry(pattern)
+            .or_default()
+            .insert(route.route.to_string());
+    }
+    for (pattern, route_names) in patterns {
+        if route_names.len() < 2 || pattern.is_empty() {
+            continue;
+        }
+        let name = |id: &StopId| stop_name(stops[id].values().next().unwrap());
+        report.identical_patterns.push(IdenticalPattern {
+            routes: route_names.into_iter().collect(),
+            from: name(pattern.first().unwrap()),
+            to: name(pattern.last().unwrap()),
+            stops: pattern.len(),
+        });
+    }
+
+    report
+}
+
+impl LintReport {
+    pub fn is_empty(&self) -> bool {
+        self.missed_merges.is_empty()
+            && self.false_merges.is_empty()
+            && self.identical_patterns.is_empty()
+            && self.repeated_stops.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "No problems found".to_string();
+        }
+
+        let mut lines = Vec::new();
+        for merge in &self.missed_merges {
+            lines.push(format!(
+                "Missed merge: {} is {} and {}, {} m apart",
+                merge.name, merge.stops[0], merge.stops[1], merge.distance
+            ));
+        }
+        for merge in &self.false_merges {
+            let stops = merge
+                .stops
+                .iter()
+                .map(|(id, name)| format!("{} ({})", name, id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let distance = merge
+                .distance
+                .map(|e| format!(", up to {} m apart", e))
+                .unwrap_or_default();
+            lines.push(format!(
+                "False merge: {} is {}{}",
+                merge.id, stops, distance
+            ));
+        }
+        for pattern in &self.identical_patterns {
+            lines.push(format!(
+                "Identical stop patterns: {} ({} stops from {} to {})",
+                pattern.routes.join(", "),
+                pattern.stops,
+                pattern.from,
+                pattern.to
+            ));
+        }
+        for stop in &self.repeated_stops {
+            lines.push(format!(
+                "Repeated stop: trip {} of {} goes twice through {}",
+                stop.trip, stop.route, stop.stop
+            ));
+        }
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Progress bars, drawn on stderr above the log lines
+static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+/// Set while the full-screen preview is shown, log lines would be drawn over it
+static SILENCED: AtomicBool = AtomicBool::new(false);
+
+/// Writes the log lines on stderr, so that reports printed on stdout can be piped
+struct Logger {
+    json: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && !SILENCED.load(Ordering::Relaxed)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = if self.json {
+            serde_json::json!({
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string()
+        } else if record.level() == Level::Info {
+            record.args().to_string()
+        } else {
+            format!("[{}] {}", record.level(), record.args())
+        };
+        progress().suspend(|| eprintln!("{}", line));
+    }
+
+    fn flush(&self) {}
+}
+
+/// `quiet` only keeps warnings and errors, each `verbose` adds a level: debug, then trace
+pub fn init(quiet: bool, verbose: u8, json: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    log::set_logger(Box::leak(Box::new(Logger { json }))).unwrap();
+    log::set_max_level(level);
+
+    // Progress bars are only drawn for humans
+    let target = if quiet || json {
+        ProgressDrawTarget::hidden()
+    } else {
+        ProgressDrawTarget::stderr()
+    };
+    PROGRESS
+        .set(MultiProgress::with_draw_target(target))
+        .unwrap();
+}
+
+fn progress() -> &'static MultiProgress {
+    PROGRESS.get_or_init(MultiProgress::new)
+}
+
+pub fn progress_bar(len: usize, message: &str) -> ProgressBar {
+    let style = ProgressStyle::with_template("{msg} [{bar:40}] {pos}/{len}")
+        .unwrap()
+        .progress_chars("=> ");
+    let bar = ProgressBar::new(len as u64)
+        .with_style(style)
+        .with_message(message.to_string());
+    progress().add(bar)
+}
+
+/// For the steps whose progress is unknown, like reading a feed
+pub fn spinner(message: &str) -> ProgressBar {
+    let spinner = progress().add(ProgressBar::new_spinner().with_message(message.to_string()));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+pub fn silence(silenced: bool) {
+    SILENCED.store(silenced, Ordering::Relaxed);
+}
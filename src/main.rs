@@ -1,13 +1,53 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
 
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use git2::{Commit, Oid, Repository};
-use gtfs_structures::{Gtfs, Route, Trip};
+use gtfs_structures::{
+    DirectionType, Gtfs, LocationType, PickupDropOffType, Route, StopTime, TimepointType, Trip,
+};
 use inquire::{list_option::ListOption, validator::Validation, Confirm, MultiSelect};
+use log::{debug, error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+
+use area::{longest_run_inside, stop_inside, Area};
+use diff::diff_networks;
+use export::export_json;
+use filter::{
+    matches_agency, parse_regex, prompt_route_kinds, Agencies, RouteKind, RouteNameFilter,
+};
+use lint::lint;
+use logging::{progress_bar, spinner};
+use major_stops::keep_major_stops;
+use manifest::{read_manifest, write_manifest};
+use merge_policy::MergePolicy;
+use overrides::Overrides;
+use preview::preview;
+use stats::{network_stats, StatsFormat};
+use stop_selection::{match_stops, prompt_stops, routes_through};
+use validation::{has_enough_stops, FeedReport, OnInvalid};
+
+mod area;
+mod diff;
+mod export;
+mod filter;
+mod lint;
+mod logging;
+mod major_stops;
+mod manifest;
+mod merge_policy;
+mod overrides;
+mod preview;
+mod stats;
+mod stop_selection;
+mod validation;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The URL or path to the folder containing the GTFS files
     #[arg(short, long, default_value = "./gtfs")]
     path: String,
@@ -15,9 +55,189 @@ struct Args {
     #[arg(short, long, default_value = "./result")]
     git_dir: String,
 
-    /// To prefilter routes names, if the CLI is too slow
+    /// To prefilter routes by `route_id`, short or long name, if the CLI is too slow.
+    /// Comma-separated, `*` and `?` can be used as wildcards
     #[arg(long, default_value = "")]
     prefilter: String,
+
+    /// To prefilter routes by `route_id`, short or long name with a regular expression
+    #[arg(long, value_parser = parse_regex)]
+    prefilter_regex: Vec<String>,
+
+    /// Match the prefilters case-insensitively
+    #[arg(long)]
+    ignore_case: bool,
+
+    /// How to handle both directions of a line
+    #[arg(long, value_enum, default_value_t = DirectionMode::Canonical)]
+    directions: DirectionMode,
+
+    /// Only list routes of these kinds, e.g. `subway,tram`. Asked interactively if not given
+    #[arg(long, value_enum, value_delimiter = ',')]
+    route_type: Vec<RouteKind>,
+
+    /// Only list routes operated by these agencies, given by `agency_id` or name
+    #[arg(long, value_delimiter = ',')]
+    agency: Vec<String>,
+
+    /// Only list routes with stops in this bounding box, given as `min_lon,min_lat,max_lon,max_lat`
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<Area>,
+
+    /// Only list routes with stops in the polygons of this GeoJSON file
+    #[arg(long, value_parser = Area::from_geojson)]
+    polygon: Option<Area>,
+
+    /// Only keep the longest run of consecutive stops of each route inside the area
+    #[arg(long)]
+    trim_to_area: bool,
+
+    /// Only list the routes serving these stops, given by ID or by a part of their name
+    #[arg(long, value_delimiter = ',')]
+    stop: Vec<String>,
+
+    /// Pick the stops interactively, and only list the routes serving them
+    #[arg(long)]
+    by_stop: bool,
+
+    /// With `--stop` or `--by-stop`, also list the routes reachable with this many transfers
+    #[arg(long, default_value_t = 0)]
+    transfers: usize,
+
+    /// Preview the graph in a full-screen view before creating the repository
+    #[arg(long)]
+    preview: bool,
+
+    /// Update the repository built by a previous run: the selected routes replace the
+    /// previous ones, only the branches of changed routes are rebuilt
+    #[arg(long)]
+    update: bool,
+
+    /// Build one generation of the network per GTFS snapshot (directory or zip), oldest first.
+    /// The routes are selected from the latest one, `--path` is ignored
+    #[arg(long, conflicts_with = "update")]
+    snapshot: Vec<String>,
+
+    /// Give every route its own commit at shared stops, merging the commit of the other
+    /// routes, so that `git log --first-parent <route>` lists exactly its stops
+    #[arg(long)]
+    first_parent: bool,
+
+    /// How to choose the branch hosting the commit of a shared stop, e.g. `list,route-type`.
+    /// The other routes merge into it. By default, the route with the smallest ID
+    #[arg(long, value_enum, value_delimiter = ',')]
+    host_priority: Vec<HostPriority>,
+
+    /// With `--host-priority list`, the routes hosting shared stops first, by ID or name
+    #[arg(long, value_delimiter = ',')]
+    host_routes: Vec<String>,
+
+    /// How to merge the routes serving a shared stop. With `--first-parent`, merges always have
+    /// two parents
+    #[arg(long, value_enum, default_value_t = MergeStrategy::Octopus)]
+    merge_strategy: MergeStrategy,
+
+    /// Only merge routes at stops served by at least this many of them
+    #[arg(long, default_value_t = 2)]
+    merge_min_routes: usize,
+
+    /// Only merge routes of compatible kinds: rail-bound ones (rail, subway, tram, funicular)
+    /// together, road ones (bus, coach, taxi) together…
+    #[arg(long)]
+    merge_compatible_types: bool,
+
+    /// Never merge routes at these stops, given by ID or name
+    #[arg(long, value_delimiter = ',')]
+    no_merge_stop: Vec<String>,
+
+    /// Only merge routes at stops belonging to a station (`location_type=1`)
+    #[arg(long)]
+    merge_at_stations_only: bool,
+
+    /// How to handle the stops where passengers cannot board or alight (`pickup_type` and
+    /// `drop_off_type`)
+    #[arg(long, value_enum, default_value_t = BoardingMode::Ignore)]
+    boarding: BoardingMode,
+
+    /// Only keep the major stops: terminals, interchanges and timepoints. The other stops are
+    /// listed in the commit of the next kept stop
+    #[arg(long)]
+    major_stops: bool,
+
+    /// With `--major-stops`, also keep at least one stop out of N
+    #[arg(long, value_name = "N", requires = "major_stops")]
+    major_stops_every: Option<usize>,
+
+    /// JSON file of manual corrections: stops to merge, split, rename or exclude from a route,
+    /// and route orientations to pin
+    #[arg(long, value_parser = Overrides::from_file)]
+    overrides: Option<Overrides>,
+
+    /// What to do with the trips that cannot be built: unknown route, no or a single stop
+    #[arg(long, value_enum, default_value_t = OnInvalid::Skip)]
+    on_invalid: OnInvalid,
+
+    /// Select every candidate route instead of prompting
+    #[arg(long)]
+    headless: bool,
+
+    /// Only print warnings and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print the details of the build, twice to trace every commit
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Print the log lines as JSON objects
+    #[arg(long, global = true)]
+    log_json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compare the routes of two feeds, with the most frequent stop pattern of every route
+    /// passing the filters, instead of creating a repository
+    Diff {
+        /// The URL or path to the older feed
+        old: String,
+        /// The URL or path to the newer feed
+        new: String,
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Look for likely mistakes of the feed in the stop patterns of the routes passing the
+    /// filters: missed or false merges of stops, identical patterns, stops visited twice
+    Lint {
+        /// Distance in meters under which stops with the same name should be the same stop
+        #[arg(long, default_value_t = 200.0)]
+        distance: f64,
+        /// Print the problems as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Select the routes as usual, then print statistics about their shared stops instead of
+    /// creating a repository
+    Stats {
+        #[arg(long, value_enum, default_value_t = StatsFormat::Text)]
+        format: StatsFormat,
+    },
+    /// Select the routes as usual, then export them, in their final order, with the planned
+    /// commits as JSON instead of creating a repository
+    Export {
+        /// The file to write, stdout if not given
+        #[arg(long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum DirectionMode {
+    /// One branch per direction of a line
+    Split,
+    /// One branch per line, the opposite direction is recorded in the branch description
+    Canonical,
 }
 
 type RouteId = String;
@@ -25,11 +245,23 @@ type StopId = String;
 type RouteName = String;
 type StopName = String;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 struct GitRoute {
     id: RouteId,
     name: RouteName,
     stops: Vec<GitStop>,
+    direction: Option<Direction>,
+    headsign: Option<String>,
+    opposite_headsign: Option<String>,
+    // Whether `fix_order` reversed the stops, i.e. they are not in travel order anymore
+    reversed: bool,
+    // Whether `fix_order` must keep the current order of the stops
+    pinned: bool,
+    // Kind and number of trips of the route, to choose the branch hosting shared stops
+    #[serde(default)]
+    kind: Option<RouteKind>,
+    #[serde(default)]
+    trips: usize,
 }
 
 impl GitRoute {
@@ -40,21 +272,163 @@ impl GitRoute {
     pub fn stop(&self, idx: usize) -> Option<&GitStop> {
         self.stops.get(idx)
     }
+
+    pub fn reverse(&mut self) {
+        // The elided stops come before the next stop, which is now the previous one
+        let mut elided = self
+            .stops
+            .iter_mut()
+            .map(|e| std::mem::take(&mut e.elided))
+            .collect::<Vec<_>>();
+        elided.rotate_left(1);
+        for (stop, mut elided) in self.stops.iter_mut().zip(elided) {
+            elided.reverse();
+            stop.elided = elided;
+        }
+        self.stops.reverse();
+        self.reversed = !self.reversed;
+    }
+
+    /// Human-readable summary of the direction, stored as the branch description
+    pub fn description(&self) -> String {
+        let mut description = match (&self.headsign, self.direction) {
+            (Some(headsign), Some(direction)) => format!(
+                "{} towards {} (direction {})",
+                self.name,
+                headsign,
+                direction.id()
+            ),
+            (Some(headsign), None) => format!("{} towards {}", self.name, headsign),
+            (None, Some(direction)) => format!("{} (direction {})", self.name, direction.id()),
+            (None, None) => self.name.clone(),
+        };
+        if let Some(opposite) = &self.opposite_headsign {
+            description.push_str(&format!("\nOpposite direction: towards {}", opposite));
+        }
+        if self.reversed {
+            description.push_str("\nStops are listed in reverse travel order.");
+        }
+        description
+    }
+}
+
+/// Direction of travel of a trip, as given by its GTFS `direction_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+enum Direction {
+    Outbound,
+    Inbound,
+}
+
+impl Direction {
+    fn id(self) -> u8 {
+        match self {
+            Direction::Outbound => 0,
+            Direction::Inbound => 1,
+        }
+    }
+
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::Outbound => Direction::Inbound,
+            Direction::Inbound => Direction::Outbound,
+        }
+    }
+}
+
+impl From<DirectionType> for Direction {
+    fn from(direction: DirectionType) -> Self {
+        match direction {
+            DirectionType::Outbound => Direction::Outbound,
+            DirectionType::Inbound => Direction::Inbound,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 struct GitStop {
     id: StopId,
     name: StopName,
+    // Names of the minor stops before this one, dropped by `--major-stops`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    elided: Vec<StopName>,
+    // Whether passengers cannot board or alight, with `--boarding mark`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    restriction: Option<Restriction>,
+}
+
+/// Stops where passengers cannot board, alight or both (`pickup_type` and `drop_off_type` 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Restriction {
+    DropOffOnly,
+    PickUpOnly,
+    NoService,
+}
+
+impl Restriction {
+    fn of(stop_time: &StopTime) -> Option<Restriction> {
+        let pickup = stop_time.pickup_type != PickupDropOffType::NotAvailable;
+        let drop_off = stop_time.drop_off_type != PickupDropOffType::NotAvailable;
+        match (pickup, drop_off) {
+            (true, true) => None,
+            (false, true) => Some(Restriction::DropOffOnly),
+            (true, false) => Some(Restriction::PickUpOnly),
+            (false, false) => Some(Restriction::NoService),
+        }
+    }
 }
 
-fn initialize_repo() -> Repository {
-    println!("Creating the Git repository in ./result");
-    let repo = Repository::init("./result").unwrap();
-    println!("Repository created");
+impl std::fmt::Display for Restriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Restriction::DropOffOnly => write!(f, "Drop-off only"),
+            Restriction::PickUpOnly => write!(f, "Pick-up only"),
+            Restriction::NoService => write!(f, "No pick-up or drop-off"),
+        }
+    }
+}
+
+/// How to handle the stops where passengers cannot board or alight
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum BoardingMode {
+    /// Stops are committed whatever their `pickup_type` and `drop_off_type`
+    #[default]
+    Ignore,
+    /// Leave out the stops where the vehicle only passes
+    Skip,
+    /// Routes do not merge at the stops where they only pass
+    NoMerge,
+    /// Write "Drop-off only", "Pick-up only"… in the commits of the restricted stops
+    Mark,
+}
+
+/// Platforms of a same station share the part of their ID before the first `:`
+fn normalize_stop_id(stop_id: &str) -> StopId {
+    stop_id.split(":").next().unwrap().to_string()
+}
+
+fn initialize_repo(git_dir: &str) -> Repository {
+    info!("Creating the Git repository in {}", git_dir);
+    let repo = Repository::init(git_dir).unwrap();
+    debug!("Repository created");
     repo
 }
 
+fn open_repo(git_dir: &str) -> Repository {
+    info!("Opening the Git repository in {}", git_dir);
+    Repository::open(git_dir).unwrap()
+}
+
+fn delete_branch(repo: &Repository, branch: &str) {
+    if let Ok(mut reference) = repo.find_reference(&format!("refs/heads/{}", branch)) {
+        reference.delete().unwrap();
+    }
+    let _ = repo
+        .config()
+        .unwrap()
+        .remove(&format!("branch.{}.description", branch));
+}
+
 fn add_commit_to_head(repo: &Repository, branch: &str, commit: Oid) {
     repo.reference(
         format!("refs/heads/{}", branch).as_str(),
@@ -65,23 +439,19 @@ fn add_commit_to_head(repo: &Repository, branch: &str, commit: Oid) {
     .unwrap();
 }
 
-fn commit(repo: &Repository, message: &str, parents: Vec<Oid>, branch: &str) -> Oid {
-    println!(
-        "Creating commit with message {} with parents {:?} on branch {}",
-        message, parents, branch
-    );
-    let refs = format!("refs/heads/{}", branch);
-    repo.set_head(&refs).unwrap();
-    // repo.checkout_head(None).unwrap();
-
-    let reffffs = repo
-        .references()
+fn describe_branch(repo: &Repository, branch: &str, description: &str) {
+    repo.config()
         .unwrap()
-        .map(|e| e.unwrap())
-        .collect::<Vec<_>>();
-    for ref_ in reffffs {
-        println!("Ref: {:?} {:?}", ref_.name(), ref_.target());
-    }
+        .set_str(&format!("branch.{}.description", branch), description)
+        .unwrap();
+}
+
+fn commit(repo: &Repository, message: &str, parents: Vec<Oid>) -> Oid {
+    trace!(
+        "Creating commit with message {} with parents {:?}",
+        message,
+        parents
+    );
 
     let index = repo.index().unwrap().write_tree().unwrap();
     let tree = repo.find_tree(index).unwrap();
@@ -93,15 +463,169 @@ fn commit(repo: &Repository, message: &str, parents: Vec<Oid>, branch: &str) ->
         .collect();
     let parents_refs: Vec<&Commit> = parents.iter().collect();
 
-    repo.commit(
-        Some(&format!("refs/heads/{}", branch)),
-        &sig,
-        &sig,
-        message,
-        &tree,
-        &parents_refs,
-    )
-    .unwrap()
+    repo.commit(None, &sig, &sig, message, &tree, &parents_refs)
+        .unwrap()
+}
+
+/// Index of a commit in a `Plan`
+type CommitIdx = usize;
+
+/// A commit to create, its parents are earlier commits of the plan
+#[derive(Debug, Clone)]
+struct PlannedCommit {
+    message: String,
+    // The stop the commit stands for
+    stop: StopId,
+    parents: Vec<CommitIdx>,
+    // The route whose branch hosts the commit
+    route: RouteId,
+}
+
+/// The commits of the repository, in creation order, and the last commit of every route
+#[derive(Debug, Clone, Default)]
+struct Plan {
+    commits: Vec<PlannedCommit>,
+    heads: HashMap<RouteId, CommitIdx>,
+    // Parents dropped from merges because they were ancestors of other parents
+    removed_parents: usize,
+    // Whether the first parent of a commit must be kept, even if it is redundant
+    keep_first_parents: bool,
+}
+
+/// How the commits of the routes are planned
+#[derive(Debug, Clone, Default)]
+struct PlanOptions {
+    // Every route gets its own commit at shared stops, see `plan_first_parent_merge`
+    first_parent: bool,
+    // Criteria to choose the branch hosting the commit of a shared stop, by importance
+    host_priority: Vec<HostPriority>,
+    // Routes, by ID or name, that host shared stops first with `HostPriority::List`
+    host_routes: Vec<String>,
+    merge_strategy: MergeStrategy,
+}
+
+impl From<&Args> for PlanOptions {
+    fn from(args: &Args) -> Self {
+        PlanOptions {
+            first_parent: args.first_parent,
+            host_priority: args.host_priority.clone(),
+            host_routes: args.host_routes.clone(),
+            merge_strategy: args.merge_strategy,
+        }
+    }
+}
+
+impl PlanOptions {
+    /// Orders routes from the one that should host a shared stop to the one that should join it.
+    /// Routes are ordered by ID when the criteria do not tell them apart.
+    fn compare_hosts(&self, a: &GitRoute, b: &GitRoute) -> std::cmp::Ordering {
+        let list_rank = |route: &GitRoute| {
+            let base_id = route.id.split('#').next().unwrap();
+            self.host_routes
+                .iter()
+                .position(|e| *e == route.id || e == base_id || *e == route.name)
+                .unwrap_or(usize::MAX)
+        };
+        let kind_rank = |route: &GitRoute| route.kind.map_or(u8::MAX, RouteKind::host_rank);
+
+        self.host_priority
+            .iter()
+            .map(|priority| match priority {
+                HostPriority::List => list_rank(a).cmp(&list_rank(b)),
+                HostPriority::RouteType => kind_rank(a).cmp(&kind_rank(b)),
+                HostPriority::Trips => b.trips.cmp(&a.trips),
+            })
+            .fold(std::cmp::Ordering::Equal, std::cmp::Ordering::then)
+            .then_with(|| a.id.cmp(&b.id))
+    }
+}
+
+/// How the routes serving a shared stop are merged
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum MergeStrategy {
+    /// A single commit merging all the routes
+    #[default]
+    Octopus,
+    /// A chain of two-parent merges, one per route joining the host
+    Pairwise,
+}
+
+/// Criterion to choose the branch hosting the commit of a shared stop
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum HostPriority {
+    /// The routes of `--host-routes` first, in this order
+    List,
+    /// Rail first, then subway, tram… and buses last
+    RouteType,
+    /// The routes with the most trips first
+    Trips,
+}
+
+impl Plan {
+    fn commit(&mut self, stop: &GitStop, parents: Vec<CommitIdx>, route: &RouteId) -> CommitIdx {
+        debug_assert!(
+            parents.iter().collect::<HashSet<_>>().len() == parents.len(),
+            "Duplicate parents"
+        );
+        let parents = self.reduce_parents(parents);
+        trace!(
+            "Planning commit for stop {} with parents {:?} on route {}",
+            stop.name,
+            parents,
+            route
+        );
+        let mut message = stop.name.clone();
+        if let Some(restriction) = stop.restriction {
+            message = format!("{}\n\n{}", message, restriction);
+        }
+        if !stop.elided.is_empty() {
+            message = format!("{}\n\nVia {}", message, stop.elided.join(", "));
+        }
+        self.commits.push(PlannedCommit {
+            message,
+            stop: stop.id.clone(),
+            parents,
+            route: route.clone(),
+        });
+        let commit = self.commits.len() - 1;
+        self.heads.insert(route.clone(), commit);
+        commit
+    }
+
+    fn move_head(&mut self, route: &RouteId, commit: CommitIdx) {
+        self.heads.insert(route.clone(), commit);
+    }
+
+    /// A parent that is an ancestor of another one only adds a redundant edge
+    fn reduce_parents(&mut self, parents: Vec<CommitIdx>) -> Vec<CommitIdx> {
+        let reduced = parents
+            .iter()
+            .enumerate()
+            .filter(|(idx, a)| {
+                (*idx == 0 && self.keep_first_parents)
+                    || !parents.iter().any(|b| *a != b && self.is_ancestor(**a, *b))
+            })
+            .map(|(_, a)| *a)
+            .collect::<Vec<_>>();
+        self.removed_parents += parents.len() - reduced.len();
+        reduced
+    }
+
+    fn is_ancestor(&self, ancestor: CommitIdx, commit: CommitIdx) -> bool {
+        let mut visited = HashSet::new();
+        let mut queue = vec![commit];
+        while let Some(commit) = queue.pop() {
+            if commit == ancestor {
+                return true;
+            }
+            // Parents are always planned before their children
+            if commit < ancestor || !visited.insert(commit) {
+                continue;
+            }
+            queue.extend(&self.commits[commit].parents);
+        }
+        false
+    }
 }
 
 fn get_conflicts(routes: &HashMap<RouteId, GitRoute>) -> HashMap<StopId, Vec<RouteId>> {
@@ -122,7 +646,7 @@ fn get_conflicts(routes: &HashMap<RouteId, GitRoute>) -> HashMap<StopId, Vec<Rou
 }
 
 fn build_route_alone(
-    repo: &Repository,
+    plan: &mut Plan,
     route: &GitRoute,
     previous: RouteBuildState,
     conflicts: &HashSet<StopId>,
@@ -143,23 +667,25 @@ fn build_route_alone(
     let mut state = previous;
 
     for stop_idx in from_stop_idx..route.stops().len() {
-        println!(
+        trace!(
             "Trying to build stop {} for route {}. Current state: {:?}",
-            stop_idx, route.name, state
+            stop_idx,
+            route.name,
+            state
         );
         let stop = route.stop(stop_idx).unwrap();
         if conflicts.contains(&stop.id) {
-            println!("Stop {} is in conflict", stop.name);
+            trace!("Stop {} is in conflict", stop.name);
             break;
         }
 
-        println!("Creating stop {} for route {}", stop.name, route.name);
+        trace!("Creating stop {} for route {}", stop.name, route.name);
         let parent = if let Some(commit) = state.commit() {
             vec![*commit]
         } else {
             vec![]
         };
-        let commit = commit(repo, &stop.name.to_string(), parent, &route.name);
+        let commit = plan.commit(stop, parent, &route.id);
         state = state.did_stop(stop_idx, commit)
     }
 
@@ -170,13 +696,13 @@ fn build_route_alone(
 enum RouteBuildState {
     // Untouched, not created yet. The usize is the length of the route.
     Untouched(usize),
-    Built(Oid),
+    Built(CommitIdx),
     // Built until stop (index), inclusive. Has 2nd usize stops
-    Pending(usize, usize, Oid),
+    Pending(usize, usize, CommitIdx),
 }
 
 impl RouteBuildState {
-    fn commit(&self) -> Option<&Oid> {
+    fn commit(&self) -> Option<&CommitIdx> {
         match self {
             RouteBuildState::Built(commit) => Some(commit),
             RouteBuildState::Pending(_, _, commit) => Some(commit),
@@ -184,7 +710,7 @@ impl RouteBuildState {
         }
     }
 
-    fn did_commit(self, commit: Oid) -> RouteBuildState {
+    fn did_commit(self, commit: CommitIdx) -> RouteBuildState {
         match self {
             RouteBuildState::Built(_) => panic!("The route has already been built"),
             RouteBuildState::Pending(idx, max, _) if idx == max - 2 => {
@@ -195,7 +721,7 @@ impl RouteBuildState {
         }
     }
 
-    fn did_stop(self, index: usize, commit: Oid) -> RouteBuildState {
+    fn did_stop(self, index: usize, commit: CommitIdx) -> RouteBuildState {
         match self {
             RouteBuildState::Built(_) => panic!("The route has already been built"),
             RouteBuildState::Untouched(_) if index > 0 => {
@@ -264,13 +790,18 @@ fn find_dependencies(
         if routes.len() == 1 {
             dependencies.remove(&stop_id);
         }
+        // The first route hosts the commit, it must not depend on the order of a `HashMap`
+        if let Some(routes) = dependencies.get_mut(&stop_id) {
+            routes.sort();
+        }
     }
 
     dependencies
 }
 
-/// Does not
-fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
+/// Reverses some routes so that all routes go through their common stops in the same order.
+/// Pinned routes are never reversed.
+fn fix_order(routes: HashMap<RouteId, GitRoute>) -> Result<HashMap<RouteId, GitRoute>, String> {
     fn same_order(a: &GitRoute, b: &GitRoute) -> bool {
         // Make sure that both routes take the stops in the same order
 
@@ -295,7 +826,11 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
 
     let mut reference_routes: Vec<(RouteId, GitRoute)> = Vec::new();
 
-    for route in routes.clone() {
+    // Pinned routes first, as they are the references that cannot move
+    let mut routes = routes.into_iter().collect::<Vec<_>>();
+    routes.sort_by(|a, b| (!a.1.pinned, &a.0).cmp(&(!b.1.pinned, &b.0)));
+
+    for route in routes {
         if reference_routes.is_empty() {
             reference_routes.push(route);
             continue;
@@ -304,10 +839,15 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
         // consider route 1, does it have same order with all previous routes?
         if reference_routes.iter().all(|e| same_order(&route.1, &e.1)) {
             reference_routes.push(route);
+        } else if route.1.pinned {
+            return Err(format!(
+                "Pinned route {} does not go through its common stops in the same order as other pinned routes",
+                route.1.name
+            ));
         } else {
             // otherwise, if flipped, does it have same order with all previous routes?
             let mut flipped = route.1.clone();
-            flipped.stops.reverse();
+            flipped.reverse();
             if reference_routes.iter().all(|e| same_order(&flipped, &e.1)) {
                 reference_routes.push((route.0.clone(), flipped));
             } else {
@@ -317,18 +857,21 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
                 // Who's being a naughty boy here in our reference routes?
                 let naughty_boys = reference_routes
                     .iter()
-                    .filter(|e| !same_order(&flipped, &e.1))
+                    .filter(|e| !same_order(&route.1, &e.1))
                     .collect::<Vec<_>>();
                 // can we flip the naughty boys?
                 let flipped_naughty = naughty_boys
                     .iter()
+                    .filter(|e| !e.1.pinned)
                     .map(|e| {
                         let mut flipped = e.1.clone();
-                        flipped.stops.reverse();
+                        flipped.reverse();
                         (e.0.clone(), flipped)
                     })
                     .collect::<HashMap<_, _>>();
 
+                let flipped_naughty_count = flipped_naughty.len();
+
                 // proposal for new reference routes
                 let mut new_reference = reference_routes
                     .clone()
@@ -341,7 +884,9 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
                 // check if all of those are valid
 
                 // Verify that there are no more conflicts with current addition
-                if new_reference.iter().all(|e| same_order(&route.1, &e.1)) {
+                if flipped_naughty_count == naughty_boys.len()
+                    && new_reference.iter().all(|e| same_order(&route.1, &e.1))
+                {
                     // verify that there is no more conflicts between each routes
                     let mut successful_proposal = true;
                     for r1 in &new_reference {
@@ -362,11 +907,11 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
                     }
                 }
 
-                println!(
+                debug!(
                     "Could not unify stops order for route {}. Details:",
                     route.1.name
                 );
-                println!(
+                debug!(
                     "Stops for route {}: {:?}",
                     route.1.name,
                     route
@@ -377,27 +922,30 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
                         .collect::<Vec<_>>()
                 );
                 for e in &reference_routes {
-                    println!(
+                    debug!(
                         "({:?}) {}: {:?}",
                         same_order(&route.1, &e.1),
                         e.1.name,
                         e.1.stops.iter().map(|e| e.name.clone()).collect::<Vec<_>>()
                     );
-                    println!(
+                    debug!(
                         "({:?},R) {}: {:?}",
                         same_order(&flipped, &e.1),
                         e.1.name,
                         e.1.stops.iter().map(|e| e.name.clone()).collect::<Vec<_>>()
                     );
                 }
-                panic!("Could not unify stops order for route {}", route.1.name);
+                return Err(format!(
+                    "Could not unify stops order for route {}",
+                    route.1.name
+                ));
             }
         }
     }
 
-    println!("Decided order:");
+    debug!("Decided order:");
     for route in &reference_routes {
-        println!(
+        debug!(
             "{}: {:?}",
             route.1.name,
             route
@@ -409,31 +957,134 @@ fn fix_order(routes: HashMap<RouteId, GitRoute>) -> HashMap<RouteId, GitRoute> {
         );
     }
 
-    reference_routes.into_iter().collect()
+    Ok(reference_routes.into_iter().collect())
 }
 
-fn build_repository(routes: HashMap<RouteId, GitRoute>) {
-    let repo = initialize_repo();
+/// Plans a single commit for the stop shared by the routes, on the branch of one of them
+fn plan_merge(
+    plan: &mut Plan,
+    routes: &HashMap<RouteId, GitRoute>,
+    stop: &GitStop,
+    routes_state: &[(RouteId, RouteBuildState)],
+    parents: Vec<CommitIdx>,
+    strategy: MergeStrategy,
+) -> HashMap<RouteId, CommitIdx> {
+    let dep_routes = routes_state.iter().map(|e| &e.0).collect::<Vec<_>>();
+
+    // Choose a route's branch to put all the commits. Inside a shared corridor, the
+    // routes all point to its previous commit: keep the same host, without merging.
+    let corridor_host = match parents[..] {
+        [parent] => Some(&plan.commits[parent].route).filter(|e| dep_routes.contains(e)),
+        _ => None,
+    };
+    if corridor_host.is_some() {
+        debug!("Continuing the shared corridor at {}", stop.name);
+    }
+    let host_route = corridor_host.unwrap_or(dep_routes[0]).clone();
+    trace!("Host route: {}", host_route);
+
+    let parents = plan.reduce_parents(parents);
+    let commit = match strategy {
+        MergeStrategy::Pairwise if parents.len() > 2 => {
+            // The routes join the host one after the other, in the order of priority. Each
+            // merge of the chain tells which routes join there.
+            let mut commit = parents[0];
+            for parent in &parents[1..] {
+                let mut joining = routes_state
+                    .iter()
+                    .filter(|(_, state)| state.commit() == Some(parent))
+                    .map(|(id, _)| routes.get(id).unwrap().name.as_str())
+                    .collect::<Vec<_>>();
+                if joining.is_empty() {
+                    joining.push(
+                        routes
+                            .get(&plan.commits[*parent].route)
+                            .unwrap()
+                            .name
+                            .as_str(),
+                    );
+                }
+                let verb = if joining.len() == 1 { "joins" } else { "join" };
+
+                commit = plan.commit(stop, vec![commit, *parent], &host_route);
+                let message = &mut plan.commits[commit].message;
+                *message = format!("{}\n\n{} {}", message, joining.join(", "), verb);
+            }
+            commit
+        }
+        _ => plan.commit(stop, parents, &host_route),
+    };
+    // advance heads of the other routes
+    for route in &dep_routes {
+        plan.move_head(route, commit);
+    }
+    dep_routes
+        .into_iter()
+        .map(|e| (e.clone(), commit))
+        .collect()
+}
+
+/// Plans a commit for the stop shared by the routes on every one of their branches, with the
+/// previous stop of the route as first parent, and the commit of the host route as second one
+fn plan_first_parent_merge(
+    plan: &mut Plan,
+    stop: &GitStop,
+    routes_state: &[(RouteId, RouteBuildState)],
+) -> HashMap<RouteId, CommitIdx> {
+    // Routes starting at the stop can share the commit of the host if it starts there too
+    let (host_route, host_state) = routes_state
+        .iter()
+        .find(|(_, state)| state.commit().is_none())
+        .unwrap_or(&routes_state[0]);
+    trace!("Host route: {}", host_route);
+
+    let host_commit = plan.commit(
+        stop,
+        host_state.commit().into_iter().copied().collect(),
+        host_route,
+    );
+    routes_state
+        .iter()
+        .map(|(route, state)| {
+            let commit = match state.commit() {
+                _ if route == host_route => host_commit,
+                Some(previous) => plan.commit(stop, vec![*previous, host_commit], route),
+                None => {
+                    plan.move_head(route, host_commit);
+                    host_commit
+                }
+            };
+            (route.clone(), commit)
+        })
+        .collect()
+}
 
-    println!("Fixing order of the routes…");
-    let routes = fix_order(routes);
+/// Plans the commits of the routes, whose stops must already be in a common order
+fn plan_repository(
+    routes: &HashMap<RouteId, GitRoute>,
+    options: &PlanOptions,
+) -> Result<Plan, String> {
+    let mut plan = Plan {
+        keep_first_parents: options.first_parent,
+        ..Plan::default()
+    };
 
-    let conflicts: HashMap<StopId, Vec<RouteId>> = get_conflicts(&routes);
-    let mut states: HashMap<RouteId, RouteBuildState> = initialize_states(&routes);
+    let conflicts: HashMap<StopId, Vec<RouteId>> = get_conflicts(routes);
+    let mut states: HashMap<RouteId, RouteBuildState> = initialize_states(routes);
 
-    println!("Conflicts: {:?}", conflicts);
+    trace!("Conflicts: {:?}", conflicts);
 
     // Bootstrap the routes
-    for route in &routes {
-        println!("Building route {}", route.1.name);
+    for route in routes {
+        debug!("Building route {}", route.1.name);
         let state = states.get(route.0).unwrap();
         let state = build_route_alone(
-            &repo,
+            &mut plan,
             route.1,
             state.clone(),
             &conflicts.keys().cloned().collect(),
         );
-        println!("New state for route {}: {:?}", route.1.name, state);
+        trace!("New state for route {}: {:?}", route.1.name, state);
         states.insert(route.0.clone(), state);
     }
 
@@ -443,42 +1094,42 @@ fn build_repository(routes: HashMap<RouteId, GitRoute>) {
             .iter()
             .all(|(_, state)| matches!(state, RouteBuildState::Built(_)))
         {
-            println!("All routes have been built");
+            debug!("All routes have been built");
             break;
         }
 
-        println!("Checking for conflicts…");
+        trace!("Checking for conflicts…");
         // Find the dependencies required to build a stop
-        let dependencies = find_dependencies(&routes, &states);
+        let dependencies = find_dependencies(routes, &states);
 
-        println!("Entering conflict mode…");
+        trace!("Entering conflict mode…");
 
-        println!("Dependencies: {:?}", dependencies);
+        trace!("Dependencies: {:?}", dependencies);
         let mut built_something = false;
 
-        for (dep_stop_id, dep_routes) in dependencies {
+        for (dep_stop_id, mut dep_routes) in dependencies {
             let target = conflicts.get(&dep_stop_id).unwrap();
-            let stop_name = routes
+            let stop = routes
                 .get(target.first().unwrap())
                 .unwrap()
                 .stops()
                 .iter()
                 .find(|e| e.id == dep_stop_id)
                 .unwrap()
-                .name
                 .clone();
             // We have not built all the dependencies yet
             if target.len() != dep_routes.len() {
-                println!(
+                trace!(
                     "Not all dependencies have been built yet for stop {} ({})",
-                    stop_name, dep_stop_id
+                    stop.name,
+                    dep_stop_id
                 );
                 continue;
             }
 
-            println!(
+            debug!(
                 "Creating common stop for {} and lines {}",
-                stop_name,
+                stop.name,
                 dep_routes
                     .iter()
                     .map(|e| routes.get(e).unwrap().name.clone())
@@ -486,13 +1137,33 @@ fn build_repository(routes: HashMap<RouteId, GitRoute>) {
                     .join(", ")
             );
             // build common stop
-
-            // Choose a route's branch to put all the commits
-            let host_route = dep_routes.first().unwrap();
-            let host_route_name = routes.get(host_route).unwrap().name.as_str();
-            let other_routes = dep_routes.iter().skip(1).collect::<Vec<_>>();
-
-            println!("Host route: {}", routes.get(host_route).unwrap().name);
+            dep_routes.sort_by(|a, b| {
+                options.compare_hosts(routes.get(a).unwrap(), routes.get(b).unwrap())
+            });
+
+            // Each route elides its own stops before the shared one, they are added to the
+            // commits below
+            let mut stop = GitStop {
+                elided: Vec::new(),
+                ..stop
+            };
+            let mut elided: HashMap<&RouteId, &Vec<StopName>> = HashMap::new();
+            // The stop is only restricted if it is on every route
+            let mut restrictions = HashSet::new();
+            for dep_route in &dep_routes {
+                let route_stop = routes
+                    .get(dep_route)
+                    .unwrap()
+                    .stops()
+                    .iter()
+                    .find(|e| e.id == dep_stop_id)
+                    .unwrap();
+                elided.insert(dep_route, &route_stop.elided);
+                restrictions.insert(route_stop.restriction);
+            }
+            if restrictions.len() > 1 {
+                stop.restriction = None;
+            }
 
             // Get all their states, to get their oid
             let routes_state = states
@@ -504,28 +1175,70 @@ fn build_repository(routes: HashMap<RouteId, GitRoute>) {
                     RouteBuildState::Untouched(_) => (id.clone(), state.clone()),
                 })
                 .collect::<HashMap<_, _>>();
-            println!("Preparing commit…");
-            let mut parents: Vec<Oid> = Vec::new();
+            trace!("Preparing commit…");
+            let mut parents: Vec<CommitIdx> = Vec::new();
             for dep_route in &dep_routes {
                 let state = routes_state.get(dep_route).unwrap();
                 if let Some(commit) = state.commit() {
-                    parents.push(*commit);
+                    if !parents.contains(commit) {
+                        parents.push(*commit);
+                    }
                 }
             }
-            let commit = commit(&repo, &stop_name.to_string(), parents, host_route_name);
-            // advance heads of the other routes
-            for route in other_routes {
-                let route = routes.get(route).unwrap().name.as_str();
-                add_commit_to_head(&repo, route, commit);
-            }
+            let routes_state = dep_routes
+                .iter()
+                .map(|e| (e.clone(), routes_state.get(e).unwrap().clone()))
+                .collect::<Vec<_>>();
 
-            println!("Commit created");
+            // The commit of every route at the stop
+            let commits = if options.first_parent {
+                plan_first_parent_merge(&mut plan, &stop, &routes_state)
+            } else {
+                plan_merge(
+                    &mut plan,
+                    routes,
+                    &stop,
+                    &routes_state,
+                    parents,
+                    options.merge_strategy,
+                )
+            };
+
+            trace!("Commit created");
+
+            // A commit shared by several routes tells which route elided which stops
+            let mut routes_by_commit: BTreeMap<CommitIdx, Vec<&RouteId>> = BTreeMap::new();
+            for route in &dep_routes {
+                routes_by_commit
+                    .entry(commits[route])
+                    .or_default()
+                    .push(route);
+            }
+            for (commit, commit_routes) in routes_by_commit {
+                let lines = commit_routes
+                    .iter()
+                    .filter(|e| !elided[*e].is_empty())
+                    .map(|e| match commit_routes.len() {
+                        1 => format!("Via {}", elided[*e].join(", ")),
+                        _ => format!(
+                            "{} via {}",
+                            routes.get(*e).unwrap().name,
+                            elided[*e].join(", ")
+                        ),
+                    })
+                    .collect::<Vec<_>>();
+                if !lines.is_empty() {
+                    let message = &mut plan.commits[commit].message;
+                    *message = format!("{}\n\n{}", message, lines.join("\n"));
+                }
+            }
 
             built_something = true;
 
-            println!("Updating states…");
+            trace!("Updating states…");
             for (route, prev_state) in routes_state {
-                println!(
+                let commit = *commits.get(&route).unwrap();
+                trace!(
                     "Updating state for route {}, from {:?}, to {:?}",
                     route,
                     prev_state,
@@ -535,13 +1248,13 @@ fn build_repository(routes: HashMap<RouteId, GitRoute>) {
             }
 
             // Continue building the routes
-            println!("Finished solving the conflict, continuing building the routes…");
+            trace!("Finished solving the conflict, continuing building the routes…");
             for route in dep_routes {
                 let route = routes.get(&route).unwrap();
-                println!("Building route {}", route.name);
+                debug!("Building route {}", route.name);
                 let state = states.get(&route.id).unwrap();
                 let state = build_route_alone(
-                    &repo,
+                    &mut plan,
                     route,
                     state.clone(),
                     &conflicts.keys().cloned().collect(),
@@ -551,7 +1264,7 @@ fn build_repository(routes: HashMap<RouteId, GitRoute>) {
         }
 
         if !built_something {
-            println!("Infinite loop detected. Done until this:");
+            error!("Infinite loop detected. Done until this:");
             for route_state in &states {
                 let route_name = routes.get(route_state.0).unwrap().name.clone();
                 let start_stop = routes
@@ -588,93 +1301,788 @@ fn build_repository(routes: HashMap<RouteId, GitRoute>) {
                         route_name, start_stop, end_stop
                     ),
                 };
-                println!("{:?}", state);
+                error!("{:?}", state);
+            }
+            return Err("Infinite loop detected".to_string());
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Creates the planned commits, then points the branch of every route to its last commit.
+/// Returns the last commit of every route.
+fn write_plan(
+    repo: &Repository,
+    routes: &HashMap<RouteId, GitRoute>,
+    plan: &Plan,
+) -> HashMap<RouteId, Oid> {
+    let progress = progress_bar(plan.commits.len(), "Creating commits");
+    let mut oids: Vec<Oid> = Vec::with_capacity(plan.commits.len());
+    let mut created: HashSet<(String, Vec<Oid>)> = HashSet::new();
+    for planned in &plan.commits {
+        let parents = planned.parents.iter().map(|e| oids[*e]).collect::<Vec<_>>();
+        // Identical commits get the same ID: routes kept apart at a stop would share it
+        let mut message = planned.message.clone();
+        if !created.insert((message.clone(), parents.clone())) {
+            let route = &routes.get(&planned.route).unwrap().name;
+            message = format!("{}\n\n{}", message, route);
+        }
+        oids.push(commit(repo, &message, parents));
+        progress.inc(1);
+    }
+    progress.finish_and_clear();
+    info!(
+        "Created {} commits, {} of them merges, {} redundant merge parents removed",
+        plan.commits.len(),
+        plan.commits.iter().filter(|e| e.parents.len() > 1).count(),
+        plan.removed_parents
+    );
+
+    for (route_id, head) in &plan.heads {
+        let route = routes.get(route_id).unwrap();
+        add_commit_to_head(repo, &route.name, oids[*head]);
+        describe_branch(repo, &route.name, &route.description());
+    }
+
+    if let Some(last) = plan.commits.last() {
+        let branch = &routes.get(&last.route).unwrap().name;
+        repo.set_head(&format!("refs/heads/{}", branch)).unwrap();
+    }
+
+    plan.heads
+        .iter()
+        .map(|(route_id, head)| (route_id.clone(), oids[*head]))
+        .collect()
+}
+
+/// Groups of routes linked together by shared stops
+fn connected_routes(routes: &HashMap<RouteId, GitRoute>) -> Vec<BTreeSet<RouteId>> {
+    let conflicts = get_conflicts(routes);
+    let mut neighbours: HashMap<&RouteId, HashSet<&RouteId>> = HashMap::new();
+    for shared in conflicts.values() {
+        for route in shared {
+            neighbours.entry(route).or_default().extend(shared);
+        }
+    }
+
+    let mut components = Vec::new();
+    let mut visited: HashSet<&RouteId> = HashSet::new();
+    for start in routes.keys() {
+        if visited.contains(start) {
+            continue;
+        }
+        let mut component = BTreeSet::new();
+        let mut queue = vec![start];
+        while let Some(route) = queue.pop() {
+            if !visited.insert(route) {
+                continue;
+            }
+            component.insert(route.clone());
+            queue.extend(neighbours.get(route).into_iter().flatten());
+        }
+        components.push(component);
+    }
+    components
+}
+
+/// Keeps the orientation a route had in the previous build if its stops did not change,
+/// so that `fix_order` does not rebuild it reversed
+fn orient_like_previous(
+    routes: &HashMap<RouteId, GitRoute>,
+    previous: &HashMap<RouteId, GitRoute>,
+) -> HashMap<RouteId, GitRoute> {
+    routes
+        .iter()
+        .map(|(id, route)| {
+            let mut route = route.clone();
+            if let Some(old) = previous.get(id) {
+                let mut reversed = route.clone();
+                reversed.reverse();
+                if reversed.stops == old.stops && !route.pinned {
+                    route = reversed;
+                }
+                route.pinned |= route.stops == old.stops;
             }
-            panic!("Infinite loop detected");
+            (id.clone(), route)
+        })
+        .collect()
+}
+
+/// Builds the repository. When updating, only the routes linked to a changed route are
+/// rebuilt, the branches of the other ones are kept as they are.
+fn build_repository(
+    routes: HashMap<RouteId, GitRoute>,
+    repo: &Repository,
+    previous: &HashMap<RouteId, GitRoute>,
+    options: &PlanOptions,
+) {
+    info!("Fixing order of the routes…");
+    let routes = if previous.is_empty() {
+        fix_order(routes)
+    } else {
+        fix_order(orient_like_previous(&routes, previous)).or_else(|e| {
+            warn!("{}, retrying without the previous orientations", e);
+            fix_order(routes)
+        })
+    };
+    let routes = routes.unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+
+    let same_route = |id: &RouteId| match (routes.get(id), previous.get(id)) {
+        (Some(new), Some(old)) => new.name == old.name && new.stops == old.stops,
+        _ => false,
+    };
+    let previous_components = connected_routes(previous);
+    let unchanged: HashSet<RouteId> = connected_routes(&routes)
+        .into_iter()
+        .filter(|e| previous_components.contains(e) && e.iter().all(same_route))
+        .flatten()
+        .collect();
+
+    let removed = previous
+        .values()
+        .filter(|old| !routes.values().any(|new| new.name == old.name))
+        .collect::<Vec<_>>();
+    info!(
+        "Keeping {} routes, building {} routes, removing {} routes",
+        unchanged.len(),
+        routes.len() - unchanged.len(),
+        removed.len()
+    );
+    for route in removed {
+        info!("Deleting branch {}", route.name);
+        delete_branch(repo, &route.name);
+    }
+
+    let to_build = routes
+        .iter()
+        .filter(|(id, _)| !unchanged.contains(*id))
+        .map(|(id, route)| (id.clone(), route.clone()))
+        .collect::<HashMap<_, _>>();
+    let plan = plan_repository(&to_build, options).unwrap_or_else(|e| panic!("{}", e));
+    write_plan(repo, &to_build, &plan);
+
+    // HEAD may point to a deleted branch
+    if repo.head().is_err() {
+        if let Some(route) = routes.values().next() {
+            repo.set_head(&format!("refs/heads/{}", route.name))
+                .unwrap();
         }
     }
+
+    write_manifest(repo, &routes);
+}
+
+/// Name of the generation built from a snapshot: its file name, without the `.zip` extension
+fn snapshot_label(path: &str) -> String {
+    let path = Path::new(path.trim_end_matches('/'));
+    let name = if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+    {
+        path.file_stem()
+    } else {
+        path.file_name()
+    };
+    name.unwrap().to_string_lossy().to_string()
+}
+
+/// Builds one generation of the network per snapshot, oldest first. The branches of a
+/// generation are prefixed by its label, and the `history` branch gets a summary commit
+/// per snapshot, merging the branches of its generation.
+fn build_history(
+    generations: Vec<(String, HashMap<RouteId, GitRoute>)>,
+    repo: &Repository,
+    options: &PlanOptions,
+) {
+    let mut summary: Option<Oid> = None;
+    let mut previous: HashMap<RouteId, GitRoute> = HashMap::new();
+    // The previous generation before `fix_order`, to diff routes in the same orientation
+    let mut previous_raw: HashMap<RouteId, GitRoute> = HashMap::new();
+
+    for (label, routes) in generations {
+        info!("Building snapshot {} ({} routes)…", label, routes.len());
+        // Routes keep their orientation from one generation to the next when possible
+        let ordered = fix_order(orient_like_previous(&routes, &previous))
+            .or_else(|_| fix_order(routes.clone()))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let plan = plan_repository(&ordered, options).unwrap_or_else(|e| panic!("{}", e));
+        let branches = ordered
+            .iter()
+            .map(|(id, route)| {
+                let mut route = route.clone();
+                route.name = format!("{}/{}", label, route.name);
+                (id.clone(), route)
+            })
+            .collect::<HashMap<_, _>>();
+        let heads = write_plan(repo, &branches, &plan);
+
+        let message = if summary.is_none() {
+            format!(
+                "Snapshot {}\n\n{} routes: {}",
+                label,
+                routes.len(),
+                routes
+                    .values()
+                    .map(|e| e.name.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        } else {
+            let diff = diff_networks(&previous_raw, &routes);
+            format!("Snapshot {}\n\n{}", label, diff.summary())
+        };
+        let mut parents = summary.into_iter().collect::<Vec<_>>();
+        let mut tips = heads.into_values().collect::<Vec<_>>();
+        tips.sort();
+        tips.dedup();
+        // Branches contained in other branches of the generation would be redundant parents
+        let reduced = tips
+            .iter()
+            .copied()
+            .filter(|a| {
+                !tips
+                    .iter()
+                    .any(|b| a != b && repo.graph_descendant_of(*b, *a).unwrap())
+            })
+            .collect::<Vec<_>>();
+        debug!(
+            "{} redundant parents removed from the summary commit",
+            tips.len() - reduced.len()
+        );
+        parents.extend(reduced);
+        let commit = commit(repo, &message, parents);
+        add_commit_to_head(repo, "history", commit);
+
+        summary = Some(commit);
+        previous = ordered;
+        previous_raw = routes;
+    }
+
+    repo.set_head("refs/heads/history").unwrap();
 }
 
+/// A stop pattern of a route
 #[derive(Debug, Clone)]
-struct RouteDisplayWrapper(Route, Trip);
+struct RouteDisplayWrapper {
+    route: Route,
+    /// One of the trips following the pattern
+    trip: Trip,
+    agency: Option<String>,
+    /// Number of trips following the pattern
+    pattern_trips: usize,
+    /// Number of trips of the route
+    route_trips: usize,
+}
+
+impl RouteDisplayWrapper {
+    /// Only one stop pattern per route and direction can be built
+    fn selection_key(&self) -> (RouteId, Option<Direction>) {
+        (
+            self.route.id.clone(),
+            self.trip.direction_id.map(Direction::from),
+        )
+    }
+}
 
 impl std::fmt::Display for RouteDisplayWrapper {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let route = &self.0;
-        let trip = &self.1;
+        let route = &self.route;
+        let trip = &self.trip;
         let from = trip.stop_times.first().and_then(|e| e.stop.name.clone());
         let to = trip.stop_times.last().and_then(|e| e.stop.name.clone());
-        write!(f, "{}: From {:?} to {:?}", route, from, to)
+        if let Some(agency) = &self.agency {
+            write!(f, "[{}] ", agency)?;
+        }
+        write!(
+            f,
+            "{}: From {:?} to {:?} ({} stops, {} trips)",
+            route,
+            from,
+            to,
+            trip.stop_times.len(),
+            self.pattern_trips
+        )
+    }
+}
+
+/// Indices of the stop pattern with the most trips, for each route and direction
+fn most_frequent_patterns(routes: &[RouteDisplayWrapper]) -> Vec<usize> {
+    let mut best: HashMap<(RouteId, Option<Direction>), usize> = HashMap::new();
+    for (idx, route) in routes.iter().enumerate() {
+        best.entry(route.selection_key())
+            .and_modify(|e| {
+                if routes[*e].pattern_trips < route.pattern_trips {
+                    *e = idx;
+                }
+            })
+            .or_insert(idx);
+    }
+    let mut indices = best.into_values().collect::<Vec<_>>();
+    indices.sort();
+    indices
+}
+
+/// In split mode, each direction of a route is a route of its own
+fn git_route_id(route: &Route, direction: Option<Direction>, mode: DirectionMode) -> RouteId {
+    match (mode, direction) {
+        (DirectionMode::Split, Some(direction)) => format!("{}#{}", route.id, direction.id()),
+        _ => route.id.clone(),
+    }
+}
+
+/// Indices of the stop patterns the previous routes were built from. If the stops of a route
+/// changed since, its most frequent pattern is chosen.
+fn previously_built(
+    routes: &[RouteDisplayWrapper],
+    previous: &HashMap<RouteId, GitRoute>,
+    mode: DirectionMode,
+) -> Vec<usize> {
+    let mut candidates: HashMap<RouteId, Vec<usize>> = HashMap::new();
+    for (idx, route) in routes.iter().enumerate() {
+        let direction = route.trip.direction_id.map(Direction::from);
+        let id = git_route_id(&route.route, direction, mode);
+        if previous.contains_key(&id) {
+            candidates.entry(id).or_default().push(idx);
+        }
+    }
+
+    candidates
+        .into_iter()
+        .map(|(id, indices)| {
+            let old_stops = previous[&id]
+                .stops()
+                .iter()
+                .map(|e| &e.id)
+                .collect::<Vec<_>>();
+            let same_stops = |idx: &usize| {
+                let mut stops = routes[*idx]
+                    .trip
+                    .stop_times
+                    .iter()
+                    .map(|e| normalize_stop_id(&e.stop.id))
+                    .collect::<Vec<_>>();
+                if stops.iter().eq(old_stops.iter().copied()) {
+                    return true;
+                }
+                stops.reverse();
+                stops.iter().eq(old_stops.iter().copied())
+            };
+            indices.iter().copied().find(same_stops).unwrap_or_else(|| {
+                *indices
+                    .iter()
+                    .max_by_key(|e| routes[**e].pattern_trips)
+                    .unwrap()
+            })
+        })
+        .collect()
+}
+
+/// Headsigns of every direction of the candidate routes, to describe the opposite direction
+fn collect_headsigns(
+    routes: &[RouteDisplayWrapper],
+) -> HashMap<(RouteId, Option<Direction>), String> {
+    routes
+        .iter()
+        .filter_map(|route| {
+            let trip = &route.trip;
+            let direction = trip.direction_id.map(Direction::from);
+            trip.trip_headsign
+                .clone()
+                .map(|headsign| ((route.route.id.clone(), direction), headsign))
+        })
+        .collect()
+}
+
+/// Builds our internal data-structure from the selected stop patterns
+fn to_git_routes(
+    selected_routes: Vec<RouteDisplayWrapper>,
+    headsigns: &HashMap<(RouteId, Option<Direction>), String>,
+    args: &Args,
+    areas: &[Area],
+) -> HashMap<RouteId, GitRoute> {
+    let mut git_routes: HashMap<RouteId, GitRoute> = HashMap::new();
+    let mut stations: HashSet<StopId> = HashSet::new();
+    let overrides = args.overrides.clone().unwrap_or_default();
+    let mut timepoints: HashMap<RouteId, HashSet<StopId>> = HashMap::new();
+
+    for route in selected_routes {
+        let trip = route.trip;
+        let direction = trip.direction_id.map(Direction::from);
+
+        let stop_times = if args.trim_to_area && !areas.is_empty() {
+            longest_run_inside(&trip.stop_times, areas)
+        } else {
+            &trip.stop_times[..]
+        };
+
+        // Stop times are exact by default, a trip only tells its timepoints if some are not
+        let timepoints_known = stop_times
+            .iter()
+            .any(|e| e.timepoint == TimepointType::Approximate);
+        let mut route_timepoints = HashSet::new();
+        let id = git_route_id(&route.route, direction, args.directions);
+        let mut stops = stop_times
+            .iter()
+            .filter(|e| {
+                args.boarding != BoardingMode::Skip
+                    || Restriction::of(e) != Some(Restriction::NoService)
+            })
+            .map(|e| {
+                let name = if let Some(name) = e.stop.name.clone() {
+                    name.clone()
+                } else {
+                    e.stop.id.clone()
+                };
+
+                let stop_id = overrides.stop_id(&e.stop.id);
+                let name = overrides.stop_name(&stop_id, name);
+                if e.stop.parent_station.is_some() || e.stop.location_type == LocationType::StopArea
+                {
+                    stations.insert(stop_id.clone());
+                }
+
+                if timepoints_known && e.timepoint == TimepointType::Exact {
+                    route_timepoints.insert(stop_id.clone());
+                }
+
+                let restriction = Restriction::of(e);
+                let stop_id = match (args.boarding, restriction) {
+                    // The vehicle only passes, the stop is not shared with other routes
+                    (BoardingMode::NoMerge, Some(Restriction::NoService)) => {
+                        format!("{}@{}", stop_id, id)
+                    }
+                    _ => stop_id,
+                };
+
+                GitStop {
+                    id: stop_id,
+                    name,
+                    elided: Vec::new(),
+                    restriction: restriction.filter(|_| args.boarding == BoardingMode::Mark),
+                }
+            })
+            .collect::<Vec<_>>();
+        // Stops merged by the overrides can follow each other
+        stops.dedup_by(|a, b| a.id == b.id);
+
+        let route_name = if let Some(name) = route.route.long_name.clone() {
+            name
+        } else if let Some(short_name) = route.route.short_name.clone() {
+            short_name.clone()
+        } else {
+            route.route.id.clone()
+        };
+
+        let name = match (args.directions, direction) {
+            (DirectionMode::Split, Some(direction)) => {
+                let suffix = trip
+                    .trip_headsign
+                    .clone()
+                    .map(|headsign| headsign.replace(char::is_whitespace, "_"))
+                    .unwrap_or_else(|| format!("dir{}", direction.id()));
+                format!("{}-{}", route_name, suffix)
+            }
+            _ => route_name,
+        };
+
+        if let Some(existing) = git_routes.get(&id) {
+            // In canonical mode, the outbound direction (or the one without direction) wins
+            if args.directions == DirectionMode::Canonical
+                && existing.direction != Some(Direction::Inbound)
+                && direction == Some(Direction::Inbound)
+            {
+                continue;
+            }
+        }
+
+        let opposite_headsign = direction.and_then(|direction| {
+            headsigns
+                .get(&(route.route.id.clone(), Some(direction.opposite())))
+                .cloned()
+        });
+
+        let mut git_route = GitRoute {
+            id: id.clone(),
+            name,
+            stops,
+            direction,
+            headsign: trip.trip_headsign.clone(),
+            opposite_headsign,
+            reversed: false,
+            pinned: false,
+            kind: Some(route.route.route_type.into()),
+            trips: route.route_trips,
+        };
+        overrides.apply_to_route(&mut git_route, &route.route.id);
+        if !has_enough_stops(&git_route, args.on_invalid) {
+            continue;
+        }
+        timepoints.insert(id.clone(), route_timepoints);
+        git_routes.insert(id, git_route);
+    }
+
+    MergePolicy::from(args).apply(&mut git_routes, &stations);
+    if args.major_stops {
+        keep_major_stops(&mut git_routes, &timepoints, args.major_stops_every);
+    }
+    git_routes
+}
+
+/// The routes built from the most frequent stop pattern of every candidate route
+fn most_frequent_git_routes(
+    routes: &[RouteDisplayWrapper],
+    args: &Args,
+    areas: &[Area],
+) -> HashMap<RouteId, GitRoute> {
+    let selected = most_frequent_patterns(routes);
+    let selected = selected.into_iter().map(|e| routes[e].clone()).collect();
+    to_git_routes(selected, &collect_headsigns(routes), args, areas)
+}
+
+/// Prints the differences between the routes of two feeds
+fn diff_feeds(
+    old: &str,
+    new: &str,
+    json: bool,
+    args: &Args,
+    name_filter: &RouteNameFilter,
+    areas: &[Area],
+) {
+    let mut route_kinds = None;
+    let old_routes = load_candidates(old, args, name_filter, areas, &mut route_kinds);
+    let old_routes = most_frequent_git_routes(&old_routes, args, areas);
+    let new_routes = load_candidates(new, args, name_filter, areas, &mut route_kinds);
+    let new_routes = most_frequent_git_routes(&new_routes, args, areas);
+
+    let diff = diff_networks(&old_routes, &new_routes);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        println!("{}", diff.summary());
+    }
+}
+
+/// Reads a feed and lists the stop patterns of the routes passing the filters. The kinds of
+/// routes are asked the first time if they are not given.
+fn load_candidates(
+    path: &str,
+    args: &Args,
+    name_filter: &RouteNameFilter,
+    areas: &[Area],
+    route_kinds: &mut Option<HashSet<RouteKind>>,
+) -> Vec<RouteDisplayWrapper> {
+    let spinner = spinner(&format!(
+        "Reading the GTFS files from {}. This might take a while…",
+        path
+    ));
+    let gtfs = Gtfs::new(path).unwrap();
+    spinner.finish_and_clear();
+    let mut routes = gtfs.routes;
+    let mut trips = gtfs.trips;
+    info!(
+        "Found {} routes and {} trips in {}",
+        routes.len(),
+        trips.len(),
+        path
+    );
+    let agencies = Agencies::new(&gtfs.agencies);
+    let known_routes = routes.keys().cloned().collect::<HashSet<_>>();
+
+    // Filter the routes first, to drop their trips before anything is cloned
+    routes.retain(|_, route| {
+        (name_filter.is_empty() || name_filter.matches(route))
+            && (args.agency.is_empty() || matches_agency(agencies.of(route), &args.agency))
+    });
+    let route_kinds = route_kinds.get_or_insert_with(|| {
+        if !args.route_type.is_empty() {
+            args.route_type.iter().cloned().collect::<HashSet<_>>()
+        } else if args.headless {
+            HashSet::new()
+        } else {
+            prompt_route_kinds(routes.values())
+        }
+    });
+    if !route_kinds.is_empty() {
+        routes.retain(|_, route| route_kinds.contains(&route.route_type.into()));
+    }
+
+    let report = FeedReport::new(&known_routes, &routes, &trips);
+    report.log();
+    if !report.is_empty() && args.on_invalid == OnInvalid::Abort {
+        error!(
+            "{} does not pass the validation, see the warnings above",
+            path
+        );
+        std::process::exit(1);
+    }
+    for trip_id in report.invalid_trips() {
+        trips.remove(trip_id);
+    }
+    trips.retain(|_, trip| routes.contains_key(&trip.route_id));
+
+    let mut route_trips: HashMap<RouteId, usize> = HashMap::new();
+    for trip in trips.values() {
+        *route_trips.entry(trip.route_id.clone()).or_default() += 1;
     }
+
+    // Group the trips by route and stop pattern, only keeping one trip of each
+    let mut patterns: HashMap<(RouteId, Vec<StopId>), RouteDisplayWrapper> = HashMap::new();
+    for trip in trips.into_values() {
+        let route = routes.get(&trip.route_id).unwrap();
+        if !areas.is_empty() && !trip.stop_times.iter().any(|e| stop_inside(areas, &e.stop)) {
+            continue;
+        }
+        let pattern = trip.stop_times.iter().map(|e| e.stop.id.clone()).collect();
+        patterns
+            .entry((route.id.clone(), pattern))
+            .and_modify(|e| e.pattern_trips += 1)
+            .or_insert_with(|| {
+                let agency = agencies.of(route).map(|e| e.name.clone());
+                RouteDisplayWrapper {
+                    route: route.clone(),
+                    trip,
+                    agency,
+                    pattern_trips: 1,
+                    route_trips: route_trips[&route.id],
+                }
+            });
+    }
+    let mut routes = patterns.into_values().collect::<Vec<_>>();
+    routes.sort_by_key(|e| {
+        let direction = e.trip.direction_id.map(Direction::from);
+        (
+            e.agency.clone(),
+            e.route.to_string(),
+            direction,
+            std::cmp::Reverse(e.pattern_trips),
+        )
+    });
+    info!("{} stop patterns match the filters", routes.len());
+    routes
 }
 
 fn main() {
     let validator = |a: &[ListOption<&RouteDisplayWrapper>]| {
         if a.is_empty() {
-            Ok(Validation::Invalid(
+            return Ok(Validation::Invalid(
                 "At least one route must be selected".into(),
-            ))
-        } else {
-            Ok(Validation::Valid)
+            ));
+        }
+        let mut keys = HashSet::new();
+        for option in a {
+            if !keys.insert(option.value.selection_key()) {
+                return Ok(Validation::Invalid(
+                    format!(
+                        "Several stop patterns of {} in the same direction are selected, only one can be built",
+                        option.value.route
+                    )
+                    .into(),
+                ));
+            }
         }
+        Ok(Validation::Valid)
     };
 
     let args = Args::parse();
-    let filter_lines = args
-        .prefilter
-        .split(",")
-        .filter(|x| !x.is_empty())
-        .collect::<HashSet<_>>();
+    logging::init(args.quiet, args.verbose, args.log_json);
+    let options = PlanOptions::from(&args);
 
-    println!(
-        "Reading the GTFS files from {}. This might take a while…",
-        args.path
-    );
-    let gtfs = Gtfs::new(&args.path).unwrap();
-    let routes = gtfs.routes;
-    let trips = gtfs.trips;
-    println!("Found {} routes", routes.len());
-    println!("Found {} trips", trips.len());
-    let routes = {
-        trips
-            .values()
-            .filter_map(|trip| {
-                let route_id = trip.route_id.clone();
-                let route = routes.get(&route_id).unwrap();
-                if let Some(long_name) = route.long_name.as_ref() {
-                    if !filter_lines.is_empty() && !filter_lines.contains(long_name.as_str()) {
-                        return None;
-                    }
-                }
-                if let Some(short_name) = route.short_name.as_ref() {
-                    if !filter_lines.is_empty() && !filter_lines.contains(short_name.as_str()) {
-                        return None;
-                    }
-                }
-                Some(RouteDisplayWrapper(route.clone(), trip.clone()))
-            })
+    let name_filter =
+        RouteNameFilter::new(&args.prefilter, &args.prefilter_regex, args.ignore_case).unwrap();
+
+    let areas: Vec<Area> = args.bbox.iter().chain(&args.polygon).cloned().collect();
+
+    if let Some(Command::Diff { old, new, json }) = &args.command {
+        diff_feeds(old, new, *json, &args, &name_filter, &areas);
+        return;
+    }
+
+    // History mode: the routes are selected from the latest snapshot
+    let path = args.snapshot.last().unwrap_or(&args.path);
+    let mut route_kinds = None;
+    let routes = load_candidates(path, &args, &name_filter, &areas, &mut route_kinds);
+
+    if let Some(Command::Lint { distance, json }) = &args.command {
+        let report = lint(
+            &routes,
+            &args.overrides.clone().unwrap_or_default(),
+            *distance,
+        );
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        } else {
+            println!("{}", report.summary());
+        }
+        return;
+    }
+
+    // Stop-centric selection: only keep the routes through the chosen stops, preselected
+    let stop_centric = !args.stop.is_empty() || args.by_stop;
+    let routes = if stop_centric {
+        let stops = if args.by_stop {
+            prompt_stops(&routes)
+        } else {
+            match_stops(&routes, &args.stop)
+        };
+        let through = routes_through(&routes, &stops, args.transfers);
+        info!("{} routes go through the selected stops", through.len());
+        routes
+            .into_iter()
+            .filter(|e| through.contains(&e.route.id))
             .collect::<Vec<_>>()
+    } else {
+        routes
+    };
+    let mut preselected = if stop_centric {
+        most_frequent_patterns(&routes)
+    } else {
+        vec![]
     };
-    println!("routes: {}", routes.len());
 
-    let selected_routes = loop {
+    // When updating, the routes of the previous run are preselected
+    let previous = if args.update {
+        read_manifest(&open_repo(&args.git_dir))
+    } else {
+        HashMap::new()
+    };
+    preselected.extend(previously_built(&routes, &previous, args.directions));
+    preselected.sort();
+    preselected.dedup();
+
+    let headsigns = collect_headsigns(&routes);
+
+    let git_routes = loop {
+        if args.headless {
+            break most_frequent_git_routes(&routes, &args, &areas);
+        }
+
         let selected_routes = MultiSelect::new(
             "Select the routes you want to include in the repository",
             routes.clone(),
         )
+        .with_default(&preselected)
         .with_validator(validator)
         .prompt()
         .unwrap();
 
+        let git_routes = to_git_routes(selected_routes, &headsigns, &args, &areas);
+
+        if args.preview {
+            match preview(git_routes, &options) {
+                Some(git_routes) => break git_routes,
+                None => continue,
+            }
+        }
+
         println!("Selected routes: ");
-        for route in &selected_routes {
-            let trip = &route.1;
-            println!(
-                "{}: From {:?} to {:?}",
-                route,
-                trip.stop_times.first().and_then(|e| e.stop.name.clone()),
-                trip.stop_times.last().and_then(|e| e.stop.name.clone())
-            );
+        for route in git_routes.values() {
+            println!("{}", route.name);
         }
 
         let confirm = Confirm::new("Are you satisfied with the selection?")
@@ -682,55 +2090,210 @@ fn main() {
             .prompt();
 
         if let Ok(true) = confirm {
-            break selected_routes;
+            break git_routes;
         }
     };
 
-    // Build our internal data-structure
-    let mut git_routes: HashMap<RouteId, GitRoute> = HashMap::new();
+    if let Some(Command::Stats { format }) = &args.command {
+        network_stats(&git_routes).print(*format);
+        return;
+    }
 
-    for route in selected_routes {
-        let trip = route.1;
+    if let Some(Command::Export { output }) = &args.command {
+        let routes = fix_order(git_routes).unwrap_or_else(|e| panic!("{}", e));
+        let plan = plan_repository(&routes, &options).unwrap_or_else(|e| panic!("{}", e));
+        let json = export_json(&routes, &plan);
+        match output {
+            Some(path) => {
+                std::fs::write(path, json).unwrap();
+                info!("Exported the graph to {}", path);
+            }
+            None => println!("{}", json),
+        }
+        return;
+    }
 
-        let stops = trip
-            .stop_times
-            .iter()
-            .map(|e| {
-                let name = if let Some(name) = e.stop.name.clone() {
-                    name.clone()
-                } else {
-                    e.stop.id.clone()
-                };
+    if !args.snapshot.is_empty() {
+        let mut generations = Vec::new();
+        for path in &args.snapshot[..args.snapshot.len() - 1] {
+            let routes = load_candidates(path, &args, &name_filter, &areas, &mut route_kinds);
+            // Headless, every route of the snapshot is kept, even if it was removed since
+            let routes = routes
+                .into_iter()
+                .filter(|e| {
+                    let direction = e.trip.direction_id.map(Direction::from);
+                    args.headless
+                        || git_routes.contains_key(&git_route_id(
+                            &e.route,
+                            direction,
+                            args.directions,
+                        ))
+                })
+                .collect::<Vec<_>>();
+            generations.push((
+                snapshot_label(path),
+                most_frequent_git_routes(&routes, &args, &areas),
+            ));
+        }
+        generations.push((snapshot_label(path), git_routes));
 
-                let id = e.stop.id.clone();
-                let id = id
-                    .split(":")
-                    .collect::<Vec<_>>()
-                    .first()
-                    .unwrap()
-                    .to_string();
+        build_history(generations, &initialize_repo(&args.git_dir), &options);
+        return;
+    }
 
-                GitStop { id, name }
-            })
-            .collect::<Vec<_>>();
+    let repo = if args.update {
+        open_repo(&args.git_dir)
+    } else {
+        initialize_repo(&args.git_dir)
+    };
+    build_repository(git_routes, &repo, &previous, &options);
+}
 
-        let route_name = if let Some(name) = route.0.long_name.clone() {
-            name
-        } else if let Some(short_name) = route.0.short_name.clone() {
-            short_name.clone()
-        } else {
-            route.0.id.clone()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn route(id: &str, stops: &[&str]) -> (RouteId, GitRoute) {
+        let route = GitRoute {
+            id: id.to_string(),
+            name: id.to_uppercase(),
+            stops: stops
+                .iter()
+                .map(|e| GitStop {
+                    id: e.to_string(),
+                    name: e.to_string(),
+                    elided: Vec::new(),
+                    restriction: None,
+                })
+                .collect(),
+            direction: None,
+            headsign: None,
+            opposite_headsign: None,
+            reversed: false,
+            pinned: false,
+            kind: None,
+            trips: 0,
         };
+        (id.to_string(), route)
+    }
 
-        git_routes.insert(
-            route.0.id.clone(),
-            GitRoute {
-                id: route.0.id.clone(),
-                name: route_name,
-                stops,
-            },
-        );
+    /// The commits planned for a stop
+    fn commits_at<'a>(plan: &'a Plan, stop: &str) -> Vec<&'a PlannedCommit> {
+        plan.commits.iter().filter(|e| e.stop == stop).collect()
+    }
+
+    /// No merge has the same parent twice, or a parent that is an ancestor of another one
+    fn assert_reduced_parents(plan: &Plan) {
+        for (idx, commit) in plan.commits.iter().enumerate() {
+            let parents = &commit.parents;
+            for (i, a) in parents.iter().enumerate() {
+                for b in &parents[i + 1..] {
+                    assert_ne!(a, b, "commit {} has a duplicate parent", idx);
+                    assert!(
+                        !plan.is_ancestor(*a, *b) && !plan.is_ancestor(*b, *a),
+                        "commit {} has a redundant parent",
+                        idx
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shared_corridor_is_linear() {
+        let routes = HashMap::from([
+            route("a", &["a1", "x", "y", "z", "a2"]),
+            route("b", &["b1", "x", "y", "z", "b2"]),
+        ]);
+        let plan = plan_repository(&routes, &PlanOptions::default()).unwrap();
+
+        let x = commits_at(&plan, "x");
+        assert_eq!(x.len(), 1);
+        assert_eq!(x[0].parents.len(), 2);
+        for stop in ["y", "z"] {
+            let commits = commits_at(&plan, stop);
+            assert_eq!(commits.len(), 1, "{} is planned once", stop);
+            assert_eq!(commits[0].parents.len(), 1, "{} is not a merge", stop);
+            assert_eq!(commits[0].route, x[0].route, "{} stays on the host", stop);
+        }
+    }
+
+    #[test]
+    fn merges_have_no_redundant_parents() {
+        // At u, the head of b (x) is an ancestor of the head of a (t)
+        let routes = HashMap::from([
+            route("a", &["s", "x", "t", "u", "v"]),
+            route("b", &["x", "u"]),
+            route("c", &["w", "t", "u"]),
+        ]);
+        for merge_strategy in [MergeStrategy::Octopus, MergeStrategy::Pairwise] {
+            let options = PlanOptions {
+                merge_strategy,
+                ..PlanOptions::default()
+            };
+            let plan = plan_repository(&routes, &options).unwrap();
+            assert_reduced_parents(&plan);
+            assert!(plan.removed_parents > 0);
+        }
     }
 
-    build_repository(git_routes);
+    #[test]
+    fn first_parents_are_the_stops_of_the_route() {
+        let fixtures = [
+            vec![
+                route("a", &["a1", "x", "y", "a2"]),
+                route("b", &["b1", "x", "y", "b2"]),
+                route("c", &["c1", "y", "c2"]),
+            ],
+            vec![
+                route("a", &["s", "x", "t", "u", "v"]),
+                route("b", &["x", "u"]),
+                route("c", &["w", "t", "u"]),
+            ],
+        ];
+        for routes in fixtures {
+            let routes = HashMap::from_iter(routes);
+            let options = PlanOptions {
+                first_parent: true,
+                ..PlanOptions::default()
+            };
+            let plan = plan_repository(&routes, &options).unwrap();
+
+            for route in routes.values() {
+                let mut walk = Vec::new();
+                let mut commit = plan.heads.get(&route.id).copied();
+                while let Some(idx) = commit {
+                    walk.push(plan.commits[idx].stop.clone());
+                    commit = plan.commits[idx].parents.first().copied();
+                }
+                walk.reverse();
+                let stops = route
+                    .stops()
+                    .iter()
+                    .map(|e| e.id.clone())
+                    .collect::<Vec<_>>();
+                assert_eq!(walk, stops, "first parents of {}", route.id);
+            }
+        }
+    }
+
+    #[test]
+    fn pairwise_merges_name_the_joining_route() {
+        let routes = HashMap::from([
+            route("a", &["a1", "x", "a2"]),
+            route("b", &["b1", "x", "b2"]),
+            route("c", &["c1", "x", "c2"]),
+        ]);
+        let options = PlanOptions {
+            merge_strategy: MergeStrategy::Pairwise,
+            ..PlanOptions::default()
+        };
+        let plan = plan_repository(&routes, &options).unwrap();
+
+        let messages = commits_at(&plan, "x")
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["x\n\nB joins", "x\n\nC joins"]);
+    }
 }
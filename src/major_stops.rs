@@ -0,0 +1,37 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{get_conflicts, GitRoute, RouteId, StopId};
+
+/// Only keeps the major stops of the routes: terminals, interchanges, timepoints and, with
+/// `every`, at least one stop out of N. The stops in between are recorded in the next kept
+/// stop. `timepoints` are the IDs of the timepoints of each route, before the merge policies.
+pub fn keep_major_stops(
+    routes: &mut HashMap<RouteId, GitRoute>,
+    timepoints: &HashMap<RouteId, HashSet<StopId>>,
+    every: Option<usize>,
+) {
+    let interchanges = get_conflicts(routes);
+
+    for route in routes.values_mut() {
+        let timepoints = timepoints.get(&route.id);
+        let last = route.stops.len().saturating_sub(1);
+        let mut elided = Vec::new();
+        let mut stops = Vec::new();
+        for (idx, mut stop) in std::mem::take(&mut route.stops).into_iter().enumerate() {
+            // The merge policies add `@…` to the IDs
+            let feed_id = stop.id.split('@').next().unwrap();
+            let major = idx == 0
+                || idx == last
+                || interchanges.contains_key(&stop.id)
+                || timepoints.is_some_and(|e| e.contains(feed_id))
+                || every.is_some_and(|every| elided.len() + 1 >= every);
+            if major {
+                stop.elided = std::mem::take(&mut elided);
+                stops.push(stop);
+            } else {
+                elided.push(stop.name);
+            }
+        }
+        route.stops = stops;
+    }
+}
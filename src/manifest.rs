@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+use git2::Repository;
+use log::info;
+
+use crate::{GitRoute, RouteId};
+
+/// File, inside the `.git` directory, listing the routes the repository was built from
+const MANIFEST: &str = "git-sbb.json";
+
+/// Routes of a repository built by a previous run, empty if there was none
+pub fn read_manifest(repo: &Repository) -> HashMap<RouteId, GitRoute> {
+    let path = repo.path().join(MANIFEST);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap(),
+        Err(_) => {
+            info!("No routes found in {}", path.display());
+            HashMap::new()
+        }
+    }
+}
+
+pub fn write_manifest(repo: &Repository, routes: &HashMap<RouteId, GitRoute>) {
+    let content = serde_json::to_string_pretty(routes).unwrap();
+    std::fs::write(repo.path().join(MANIFEST), content).unwrap();
+}
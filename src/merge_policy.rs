@@ -0,0 +1,112 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::filter::RouteKind;
+use crate::{Args, GitRoute, RouteId, StopId};
+
+/// Where routes are allowed to join. At a stop where some routes must not merge, they get a
+/// stop ID of their own, so that they do not share the stop anymore.
+pub struct MergePolicy {
+    min_routes: usize,
+    compatible_types: bool,
+    // IDs or names of the stops where routes never merge
+    never: Vec<String>,
+    stations_only: bool,
+}
+
+impl From<&Args> for MergePolicy {
+    fn from(args: &Args) -> Self {
+        MergePolicy {
+            min_routes: args.merge_min_routes.max(2),
+            compatible_types: args.merge_compatible_types,
+            never: args.no_merge_stop.clone(),
+            stations_only: args.merge_at_stations_only,
+        }
+    }
+}
+
+/// Kinds of routes that can merge together: rail-bound, road, water, aerial…
+fn merge_group(kind: Option<RouteKind>) -> &'static str {
+    match kind {
+        Some(RouteKind::Rail | RouteKind::Subway | RouteKind::Tram | RouteKind::Funicular) => {
+            "rail"
+        }
+        Some(RouteKind::Bus | RouteKind::Coach | RouteKind::Taxi) => "road",
+        Some(RouteKind::Ferry) => "water",
+        Some(RouteKind::CableCar | RouteKind::Gondola) => "aerial",
+        Some(RouteKind::Air) => "air",
+        Some(RouteKind::Other) | None => "other",
+    }
+}
+
+impl MergePolicy {
+    /// `stations` are the stops belonging to a station (`location_type=1`)
+    pub fn apply(&self, routes: &mut HashMap<RouteId, GitRoute>, stations: &HashSet<StopId>) {
+        let mut serving: HashMap<StopId, Vec<RouteId>> = HashMap::new();
+        for route in routes.values() {
+            for stop in route.stops() {
+                serving
+                    .entry(stop.id.clone())
+                    .or_default()
+                    .push(route.id.clone());
+            }
+        }
+
+        // The new ID of the stop for each route serving it, if it changes
+        let mut renamed: HashMap<(RouteId, StopId), StopId> = HashMap::new();
+        for (stop_id, mut route_ids) in serving {
+            // A route can visit a stop twice
+            route_ids.sort();
+            route_ids.dedup();
+            if route_ids.len() < 2 {
+                continue;
+            }
+            let name = &routes
+                .get(&route_ids[0])
+                .unwrap()
+                .stops()
+                .iter()
+                .find(|e| e.id == stop_id)
+                .unwrap()
+                .name;
+            let allowed = !self
+                .never
+                .iter()
+                .any(|e| *e == stop_id || e.eq_ignore_ascii_case(name))
+                && (!self.stations_only || stations.contains(&stop_id));
+
+            let mut groups: BTreeMap<&str, Vec<&RouteId>> = BTreeMap::new();
+            for route_id in &route_ids {
+                let group = if self.compatible_types {
+                    merge_group(routes.get(route_id).unwrap().kind)
+                } else {
+                    ""
+                };
+                groups.entry(group).or_default().push(route_id);
+            }
+
+            let single_group = groups.len() == 1;
+            for (group, members) in groups {
+                let merged = allowed && members.len() >= self.min_routes;
+                if merged && single_group {
+                    continue;
+                }
+                for route_id in members {
+                    let new_id = if merged {
+                        format!("{}@{}", stop_id, group)
+                    } else {
+                        format!("{}@{}", stop_id, route_id)
+                    };
+                    renamed.insert((route_id.clone(), stop_id.clone()), new_id);
+                }
+            }
+        }
+
+        for route in routes.values_mut() {
+            for stop in &mut route.stops {
+                if let Some(new_id) = renamed.get(&(route.id.clone(), stop.id.clone())) {
+                    stop.id = new_id.clone();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{normalize_stop_id, GitRoute, StopId, StopName};
+
+/// Orientation forced on a route, whatever the other routes need
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Orientation {
+    /// The stops in travel order
+    Forward,
+    Reversed,
+}
+
+/// Manual corrections of the feed, read from a JSON file. Routes are given by `route_id` or
+/// name, stops by ID (without the platform) or name.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    /// Groups of stop IDs to treat as the same stop, which takes the ID of the first one
+    merge_stops: Vec<Vec<StopId>>,
+    /// Stops whose platforms (`<id>:…`) are different stops
+    split_stops: Vec<StopId>,
+    pin: HashMap<String, Orientation>,
+    rename_stops: HashMap<StopId, StopName>,
+    /// Stops removed from some routes
+    exclude_stops: HashMap<String, Vec<String>>,
+}
+
+impl Overrides {
+    pub fn from_file(path: &str) -> Result<Overrides, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let overrides: Overrides =
+            serde_json::from_str(&content).map_err(|e| format!("{}: {}", path, e))?;
+        if overrides.merge_stops.iter().any(Vec::is_empty) {
+            return Err(format!("{}: empty group in merge_stops", path));
+        }
+        Ok(overrides)
+    }
+
+    /// ID of the stop in the graph, from its GTFS `stop_id`
+    pub fn stop_id(&self, stop_id: &str) -> StopId {
+        let id = normalize_stop_id(stop_id);
+        let id = if self.split_stops.contains(&id) {
+            stop_id.to_string()
+        } else {
+            id
+        };
+        self.merge_stops
+            .iter()
+            .filter_map(|group| group.split_first())
+            .find(|(_, others)| others.contains(&id))
+            .map_or(id, |(first, _)| first.clone())
+    }
+
+    pub fn stop_name(&self, stop_id: &StopId, name: StopName) -> StopName {
+        self.rename_stops.get(stop_id).cloned().unwrap_or(name)
+    }
+
+    /// Removes the excluded stops of the route and pins its orientation. `gtfs_id` is the
+    /// `route_id` of the route in the feed.
+    pub fn apply_to_route(&self, route: &mut GitRoute, gtfs_id: &str) {
+        let matches = |key: &String| *key == route.id || key == gtfs_id || *key == route.name;
+
+        let excluded = self
+            .exclude_stops
+            .iter()
+            .filter(|(key, _)| matches(key))
+            .flat_map(|(_, stops)| stops)
+            .collect::<Vec<_>>();
+        route
+            .stops
+            .retain(|stop| !excluded.iter().any(|e| **e == stop.id || **e == stop.name));
+        // Removing a stop can bring two platforms of a same stop together
+        route.stops.dedup_by(|a, b| a.id == b.id);
+
+        let orientation = self
+            .pin
+            .iter()
+            .find(|(key, _)| matches(key))
+            .map(|(_, orientation)| *orientation);
+        if let Some(orientation) = orientation {
+            if let Orientation::Reversed = orientation {
+                route.reverse();
+            }
+            route.pinned = true;
+        }
+    }
+}
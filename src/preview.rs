@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::io::{stdout, Write};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use log::warn;
+
+use crate::{fix_order, logging, plan_repository, CommitIdx, GitRoute, Plan, PlanOptions, RouteId};
+
+const ROUTES_WIDTH: u16 = 32;
+const HELP: &str =
+    "↑↓ move  space toggle  f flip  s shared stops  PgUp/PgDn scroll  enter confirm  q back";
+
+/// Draws the plan like `git log --graph`, newest commit first
+fn render_graph(plan: &Plan, routes: &HashMap<RouteId, GitRoute>) -> Vec<String> {
+    let mut labels: HashMap<CommitIdx, Vec<&str>> = HashMap::new();
+    for (route_id, head) in &plan.heads {
+        labels
+            .entry(*head)
+            .or_default()
+            .push(routes.get(route_id).unwrap().name.as_str());
+    }
+
+    // Each lane waits for the commit that comes next in it
+    let mut lanes: Vec<Option<CommitIdx>> = Vec::new();
+    let mut lines = Vec::new();
+
+    for idx in (0..plan.commits.len()).rev() {
+        let commit = &plan.commits[idx];
+        let column = match lanes.iter().position(|e| *e == Some(idx)) {
+            Some(column) => column,
+            None => match lanes.iter().position(Option::is_none) {
+                Some(column) => column,
+                None => {
+                    lanes.push(None);
+                    lanes.len() - 1
+                }
+            },
+        };
+        lanes[column] = Some(idx);
+
+        let mut row = lanes
+            .iter()
+            .enumerate()
+            .map(|(lane, e)| match (lane == column, e) {
+                (true, _) => "* ",
+                (false, Some(_)) => "| ",
+                (false, None) => "  ",
+            })
+            .collect::<String>();
+        row.push_str(&commit.message);
+        if let Some(names) = labels.get_mut(&idx) {
+            names.sort();
+            row.push_str(&format!(" ({})", names.join(", ")));
+        }
+        lines.push(row);
+
+        // Other lanes waiting for this commit join it, its parents open new lanes
+        let joining = (0..lanes.len())
+            .filter(|lane| *lane != column && lanes[*lane] == Some(idx))
+            .collect::<Vec<_>>();
+        for lane in &joining {
+            lanes[*lane] = None;
+        }
+        lanes[column] = commit.parents.first().copied();
+        // Lanes this commit has an edge to: new ones, or the one already waiting for a parent
+        let mut opened = Vec::new();
+        for parent in commit.parents.iter().skip(1) {
+            if let Some(lane) = lanes.iter().position(|e| *e == Some(*parent)) {
+                if lane != column {
+                    opened.push(lane);
+                }
+                continue;
+            }
+            let lane = match lanes.iter().position(Option::is_none) {
+                Some(lane) => lane,
+                None => {
+                    lanes.push(None);
+                    lanes.len() - 1
+                }
+            };
+            lanes[lane] = Some(*parent);
+            opened.push(lane);
+        }
+
+        if !joining.is_empty() || !opened.is_empty() {
+            let row = (0..lanes.len())
+                .map(|lane| {
+                    let towards_right = (lane > column) == opened.contains(&lane);
+                    if joining.contains(&lane) || opened.contains(&lane) {
+                        if towards_right {
+                            "\\ "
+                        } else {
+                            "/ "
+                        }
+                    } else if lanes[lane].is_some() {
+                        "| "
+                    } else {
+                        "  "
+                    }
+                })
+                .collect::<String>();
+            lines.push(row.trim_end().to_string());
+        }
+
+        while lanes.last() == Some(&None) {
+            lanes.pop();
+        }
+    }
+
+    lines
+}
+
+struct Entry {
+    route: GitRoute,
+    enabled: bool,
+    flipped: bool,
+}
+
+#[derive(PartialEq)]
+enum View {
+    Graph,
+    SharedStops,
+}
+
+struct Preview {
+    entries: Vec<Entry>,
+    options: PlanOptions,
+    cursor: usize,
+    scroll: usize,
+    view: View,
+    // Ordered routes and their plan, or why they cannot be built
+    result: Result<(HashMap<RouteId, GitRoute>, Plan), String>,
+    graph: Vec<String>,
+}
+
+impl Preview {
+    fn new(routes: HashMap<RouteId, GitRoute>, options: &PlanOptions) -> Self {
+        let mut entries = routes
+            .into_values()
+            .map(|route| Entry {
+                route,
+                enabled: true,
+                flipped: false,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.route.name.cmp(&b.route.name));
+
+        let mut preview = Preview {
+            entries,
+            options: options.clone(),
+            cursor: 0,
+            scroll: 0,
+            view: View::Graph,
+            result: Err(String::new()),
+            graph: Vec::new(),
+        };
+        preview.replan();
+        preview
+    }
+
+    fn replan(&mut self) {
+        let routes = self
+            .entries
+            .iter()
+            .filter(|e| e.enabled)
+            .map(|e| {
+                let mut route = e.route.clone();
+                if e.flipped {
+                    route.reverse();
+                    route.pinned = true;
+                }
+                (route.id.clone(), route)
+            })
+            .collect::<HashMap<_, _>>();
+
+        self.result = if routes.is_empty() {
+            Err("At least one route must be selected".to_string())
+        } else {
+            fix_order(routes).and_then(|routes| {
+                plan_repository(&routes, &self.options).map(|plan| (routes, plan))
+            })
+        };
+        self.graph = match &self.result {
+            Ok((routes, plan)) => render_graph(plan, routes),
+            Err(_) => Vec::new(),
+        };
+        self.scroll = self.scroll.min(self.graph.len().saturating_sub(1));
+    }
+
+    /// Stops of the route under the cursor, with the other selected routes going through them
+    fn shared_stops(&self) -> Vec<String> {
+        let Some(entry) = self.entries.get(self.cursor) else {
+            return Vec::new();
+        };
+        let routes = match &self.result {
+            Ok((routes, _)) => routes,
+            Err(_) => return vec!["The routes cannot be built".to_string()],
+        };
+        let route = routes.get(&entry.route.id).unwrap_or(&entry.route);
+
+        let mut lines = vec![format!("Stops of {}:", route.name)];
+        for stop in route.stops() {
+            let mut others = routes
+                .values()
+                .filter(|e| e.id != route.id && e.stops().iter().any(|s| s.id == stop.id))
+                .map(|e| e.name.clone())
+                .collect::<Vec<_>>();
+            others.sort();
+            if others.is_empty() {
+                lines.push(format!("  {}", stop.name));
+            } else {
+                lines.push(format!(
+                    "* {} — shared with {}",
+                    stop.name,
+                    others.join(", ")
+                ));
+            }
+        }
+        lines
+    }
+
+    fn draw(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let body_height = height.saturating_sub(2) as usize;
+        let right_width = width.saturating_sub(ROUTES_WIDTH + 1) as usize;
+
+        queue!(out, terminal::Clear(ClearType::All))?;
+
+        for (row, entry) in self.entries.iter().enumerate().take(body_height) {
+            let line = format!(
+                "[{}] {}{}",
+                if entry.enabled { "x" } else { " " },
+                entry.route.name,
+                if entry.flipped { " (flipped)" } else { "" }
+            );
+            let line = line.chars().take(ROUTES_WIDTH as usize).collect::<String>();
+            queue!(out, cursor::MoveTo(0, row as u16))?;
+            if row == self.cursor {
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(line),
+                    SetAttribute(Attribute::Reset)
+                )?;
+            } else {
+                queue!(out, Print(line))?;
+            }
+        }
+
+        let right = match (&self.view, &self.result) {
+            (View::SharedStops, _) => self.shared_stops(),
+            (View::Graph, Ok(_)) => self.graph.clone(),
+            (View::Graph, Err(e)) => vec![format!("Cannot build the repository: {}", e)],
+        };
+        for (row, line) in right.iter().skip(self.scroll).take(body_height).enumerate() {
+            let line = line.chars().take(right_width).collect::<String>();
+            queue!(
+                out,
+                cursor::MoveTo(ROUTES_WIDTH + 1, row as u16),
+                Print(line)
+            )?;
+        }
+
+        let status = match &self.result {
+            Ok((_, plan)) => format!(
+                "{} routes, {} commits",
+                self.entries.iter().filter(|e| e.enabled).count(),
+                plan.commits.len()
+            ),
+            Err(e) => e.clone(),
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(2)),
+            Print(status.chars().take(width as usize).collect::<String>()),
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            Print(HELP.chars().take(width as usize).collect::<String>())
+        )?;
+        out.flush()
+    }
+
+    fn run(&mut self) -> std::io::Result<bool> {
+        let mut out = stdout();
+        loop {
+            self.draw(&mut out)?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let page = terminal::size()?.1.saturating_sub(2) as usize;
+            match key.code {
+                KeyCode::Up => self.cursor = self.cursor.saturating_sub(1),
+                KeyCode::Down => {
+                    self.cursor = (self.cursor + 1).min(self.entries.len().saturating_sub(1))
+                }
+                KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(page),
+                KeyCode::PageDown => {
+                    self.scroll = (self.scroll + page).min(self.graph.len().saturating_sub(1))
+                }
+                KeyCode::Char(' ') => {
+                    if let Some(entry) = self.entries.get_mut(self.cursor) {
+                        entry.enabled = !entry.enabled;
+                        self.replan();
+                    }
+                }
+                KeyCode::Char('f') => {
+                    if let Some(entry) = self.entries.get_mut(self.cursor) {
+                        entry.flipped = !entry.flipped;
+                        self.replan();
+                    }
+                }
+                KeyCode::Char('s') => {
+                    self.view = if self.view == View::Graph {
+                        View::SharedStops
+                    } else {
+                        View::Graph
+                    };
+                    self.scroll = 0;
+                }
+                KeyCode::Enter if self.result.is_ok() => return Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Raw mode and alternate screen, left when dropped, even on a panic
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> Terminal {
+        // Planning logs would be drawn over the preview
+        logging::silence(true);
+        terminal::enable_raw_mode().unwrap();
+        execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide).unwrap();
+        Terminal
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), terminal::LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+        logging::silence(false);
+    }
+}
+
+/// Shows the graph that the routes would produce, and lets the user toggle and flip routes.
+/// Returns the routes to build, in their final order, or `None` to go back to the selection.
+pub fn preview(
+    routes: HashMap<RouteId, GitRoute>,
+    options: &PlanOptions,
+) -> Option<HashMap<RouteId, GitRoute>> {
+    if routes.is_empty() {
+        warn!("No route to preview");
+        return None;
+    }
+    let mut preview = Preview::new(routes, options);
+
+    let terminal = Terminal::enter();
+    let confirmed = preview.run();
+    drop(terminal);
+
+    if !confirmed.unwrap() {
+        return None;
+    }
+
+    // The order is final: building must not reverse any route again
+    let (routes, _) = preview.result.unwrap();
+    Some(
+        routes
+            .into_iter()
+            .map(|(id, mut route)| {
+                route.pinned = true;
+                (id, route)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::route;
+    use crate::MergeStrategy;
+
+    #[test]
+    fn merges_are_drawn_with_an_edge_to_every_parent() {
+        let routes = HashMap::from([
+            route("a", &["a1", "x", "y", "z", "a2"]),
+            route("b", &["b1", "x", "y", "z", "b2"]),
+            route("c", &["c1", "x", "y", "z", "c2"]),
+            route("d", &["d1", "y", "z", "d2"]),
+        ]);
+        for (first_parent, merge_strategy) in [
+            (false, MergeStrategy::Octopus),
+            (false, MergeStrategy::Pairwise),
+            (true, MergeStrategy::Octopus),
+        ] {
+            let options = PlanOptions {
+                first_parent,
+                merge_strategy,
+                ..PlanOptions::default()
+            };
+            let plan = plan_repository(&routes, &options).unwrap();
+            let graph = render_graph(&plan, &routes);
+
+            // Commits are drawn newest first, each row followed by its edges, if any
+            let mut rows = graph.iter().enumerate().filter(|(_, e)| e.contains('*'));
+            for idx in (0..plan.commits.len()).rev() {
+                let (row, _) = rows.next().unwrap();
+                if plan.commits[idx].parents.len() > 1 {
+                    let edges = &graph[row + 1];
+                    assert!(
+                        edges.contains('/') || edges.contains('\\'),
+                        "merge {} is drawn without edges:\n{}",
+                        idx,
+                        graph.join("\n")
+                    );
+                }
+            }
+        }
+    }
+}
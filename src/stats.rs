@@ -0,0 +1,245 @@
+use std::collections::{BTreeSet, HashMap};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{get_conflicts, GitRoute, GitStop, RouteId, RouteName, StopId, StopName};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StopStats {
+    pub id: StopId,
+    pub name: StopName,
+    // Number of routes serving the stop
+    pub degree: usize,
+    pub routes: Vec<RouteName>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteStats {
+    pub name: RouteName,
+    pub stops: usize,
+    // Number of its stops served by other routes too
+    pub interchanges: usize,
+}
+
+/// The longest run of consecutive stops two routes share, in either direction
+#[derive(Debug, Clone, Serialize)]
+pub struct Corridor {
+    pub routes: [RouteName; 2],
+    pub stops: Vec<StopName>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkStats {
+    pub stops: Vec<StopStats>,
+    pub routes: Vec<RouteStats>,
+    // One per pair of routes sharing stops, longest first
+    pub corridors: Vec<Corridor>,
+    // Routes sharing no stop with any other route
+    pub isolated_routes: Vec<RouteName>,
+}
+
+/// Longest common run of consecutive stops
+fn longest_common_run<'a>(a: &[&'a GitStop], b: &[&GitStop]) -> Vec<&'a GitStop> {
+    let mut lengths = vec![0usize; b.len() + 1];
+    let (mut best, mut end) = (0, 0);
+    for (i, stop) in a.iter().enumerate() {
+        for j in (0..b.len()).rev() {
+            lengths[j + 1] = if stop.id == b[j].id {
+                lengths[j] + 1
+            } else {
+                0
+            };
+            if lengths[j + 1] > best {
+                best = lengths[j + 1];
+                end = i + 1;
+            }
+        }
+    }
+    a[end - best..end].to_vec()
+}
+
+pub fn network_stats(routes: &HashMap<RouteId, GitRoute>) -> NetworkStats {
+    let conflicts = get_conflicts(routes);
+    let name = |id: &RouteId| routes.get(id).unwrap().name.clone();
+
+    let mut stops = Vec::new();
+    let mut seen = BTreeSet::new();
+    for route in routes.values() {
+        for stop in route.stops() {
+            if !seen.insert(&stop.id) {
+                continue;
+            }
+            let names = match conflicts.get(&stop.id) {
+                Some(shared) => shared.iter().map(name).collect::<BTreeSet<_>>(),
+                None => BTreeSet::from([route.name.clone()]),
+            };
+            stops.push(StopStats {
+                id: stop.id.clone(),
+                name: stop.name.clone(),
+                degree: names.len(),
+                routes: names.into_iter().collect(),
+            });
+        }
+    }
+    stops.sort_by(|a, b| b.degree.cmp(&a.degree).then(a.name.cmp(&b.name)));
+
+    let mut route_stats = routes
+        .values()
+        .map(|route| RouteStats {
+            name: route.name.clone(),
+            stops: route.stops().len(),
+            interchanges: route
+                .stops()
+                .iter()
+                .filter(|e| conflicts.contains_key(&e.id))
+                .count(),
+        })
+        .collect::<Vec<_>>();
+    route_stats.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut pairs = BTreeSet::new();
+    for shared in conflicts.values() {
+        for a in shared {
+            for b in shared {
+                if name(a) < name(b) {
+                    pairs.insert((a, b));
+                }
+            }
+        }
+    }
+    let mut corridors = pairs
+        .into_iter()
+        .map(|(a, b)| {
+            let a = routes.get(a).unwrap();
+            let b = routes.get(b).unwrap();
+            let a_stops = a.stops().iter().collect::<Vec<_>>();
+            let mut b_stops = b.stops().iter().collect::<Vec<_>>();
+            let forward = longest_common_run(&a_stops, &b_stops);
+            b_stops.reverse();
+            let backward = longest_common_run(&a_stops, &b_stops);
+            let run = if backward.len() > forward.len() {
+                backward
+            } else {
+                forward
+            };
+            Corridor {
+                routes: [a.name.clone(), b.name.clone()],
+                stops: run.iter().map(|e| e.name.clone()).collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+    corridors.sort_by(|a, b| {
+        b.stops
+            .len()
+            .cmp(&a.stops.len())
+            .then(a.routes.cmp(&b.routes))
+    });
+
+    let isolated_routes = route_stats
+        .iter()
+        .filter(|e| e.interchanges == 0)
+        .map(|e| e.name.clone())
+        .collect();
+
+    NetworkStats {
+        stops,
+        routes: route_stats,
+        corridors,
+        isolated_routes,
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl NetworkStats {
+    pub fn to_text(&self) -> String {
+        let mut lines = vec!["Stops served by several routes:".to_string()];
+        for stop in self.stops.iter().filter(|e| e.degree > 1) {
+            lines.push(format!(
+                "  {} ({} routes): {}",
+                stop.name,
+                stop.degree,
+                stop.routes.join(", ")
+            ));
+        }
+        lines.push("Interchanges per route:".to_string());
+        for route in &self.routes {
+            lines.push(format!(
+                "  {}: {} of {} stops",
+                route.name, route.interchanges, route.stops
+            ));
+        }
+        lines.push("Longest shared corridors:".to_string());
+        for corridor in &self.corridors {
+            lines.push(format!(
+                "  {} and {} ({} stops): {}",
+                corridor.routes[0],
+                corridor.routes[1],
+                corridor.stops.len(),
+                corridor.stops.join(" - ")
+            ));
+        }
+        lines.push(format!(
+            "Isolated routes: {}",
+            if self.isolated_routes.is_empty() {
+                "none".to_string()
+            } else {
+                self.isolated_routes.join(", ")
+            }
+        ));
+        lines.join("\n")
+    }
+
+    /// A single table, one value per row: `kind,name,metric,value`
+    pub fn to_csv(&self) -> String {
+        let mut rows = vec!["kind,name,metric,value".to_string()];
+        let mut row = |kind: &str, name: &str, metric: &str, value: &str| {
+            rows.push([kind, name, metric, value].map(csv_field).join(","))
+        };
+        for stop in &self.stops {
+            row("stop", &stop.name, "id", &stop.id);
+            row("stop", &stop.name, "degree", &stop.degree.to_string());
+            row("stop", &stop.name, "routes", &stop.routes.join(";"));
+        }
+        for route in &self.routes {
+            row("route", &route.name, "stops", &route.stops.to_string());
+            let interchanges = route.interchanges.to_string();
+            row("route", &route.name, "interchanges", &interchanges);
+            let isolated = self.isolated_routes.contains(&route.name).to_string();
+            row("route", &route.name, "isolated", &isolated);
+        }
+        for corridor in &self.corridors {
+            let name = corridor.routes.join(";");
+            row(
+                "corridor",
+                &name,
+                "length",
+                &corridor.stops.len().to_string(),
+            );
+            row("corridor", &name, "stops", &corridor.stops.join(";"));
+        }
+        rows.join("\n")
+    }
+
+    pub fn print(&self, format: StatsFormat) {
+        match format {
+            StatsFormat::Text => println!("{}", self.to_text()),
+            StatsFormat::Json => println!("{}", serde_json::to_string_pretty(self).unwrap()),
+            StatsFormat::Csv => println!("{}", self.to_csv()),
+        }
+    }
+}
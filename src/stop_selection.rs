@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use inquire::{list_option::ListOption, validator::Validation, MultiSelect};
+use log::{error, info, warn};
+
+use crate::{normalize_stop_id, RouteDisplayWrapper, RouteId, StopId, StopName};
+
+/// Stops served by the candidate trips, by normalized id
+fn served_stops(routes: &[RouteDisplayWrapper]) -> BTreeMap<StopId, StopName> {
+    let mut stops: BTreeMap<StopId, StopName> = BTreeMap::new();
+    for route in routes {
+        for stop_time in &route.trip.stop_times {
+            let stop = &stop_time.stop;
+            stops
+                .entry(normalize_stop_id(&stop.id))
+                .or_insert_with(|| stop.name.clone().unwrap_or_else(|| stop.id.clone()));
+        }
+    }
+    stops
+}
+
+/// Finds the stops given by ID (as in the feed or normalized) or by a part of their name. Exits
+/// if none of them is served by the candidate routes.
+pub fn match_stops(routes: &[RouteDisplayWrapper], queries: &[String]) -> HashSet<StopId> {
+    let mut matched = HashSet::new();
+    let mut used = HashSet::new();
+    for (id, name) in served_stops(routes) {
+        let found = queries
+            .iter()
+            .filter(|query| {
+                normalize_stop_id(query) == id
+                    || name.to_lowercase().contains(&query.to_lowercase())
+            })
+            .collect::<Vec<_>>();
+        if !found.is_empty() {
+            info!("Matched stop {} ({})", name, id);
+            matched.insert(id);
+        }
+        used.extend(found);
+    }
+
+    let unmatched = queries
+        .iter()
+        .filter(|e| !used.contains(e))
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if matched.is_empty() {
+        error!("No stop of the listed routes matches {}", unmatched);
+        std::process::exit(1);
+    }
+    if !unmatched.is_empty() {
+        warn!("No stop of the listed routes matches {}", unmatched);
+    }
+    matched
+}
+
+#[derive(Debug, Clone)]
+struct StopDisplayWrapper(StopId, StopName);
+
+impl std::fmt::Display for StopDisplayWrapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.1, self.0)
+    }
+}
+
+pub fn prompt_stops(routes: &[RouteDisplayWrapper]) -> HashSet<StopId> {
+    let options = served_stops(routes)
+        .into_iter()
+        .map(|(id, name)| StopDisplayWrapper(id, name))
+        .collect::<Vec<_>>();
+
+    MultiSelect::new("Select the stops whose routes you want to include", options)
+        .with_validator(|a: &[ListOption<&StopDisplayWrapper>]| {
+            if a.is_empty() {
+                return Ok(Validation::Invalid(
+                    "At least one stop must be selected".into(),
+                ));
+            }
+            Ok(Validation::Valid)
+        })
+        .prompt()
+        .unwrap()
+        .into_iter()
+        .map(|e| e.0)
+        .collect()
+}
+
+/// Routes serving one of the stops, expanded to the routes reachable with at most
+/// `transfers` changes at a shared stop
+pub fn routes_through(
+    routes: &[RouteDisplayWrapper],
+    stops: &HashSet<StopId>,
+    transfers: usize,
+) -> HashSet<RouteId> {
+    let mut route_stops: HashMap<RouteId, HashSet<StopId>> = HashMap::new();
+    for route in routes {
+        route_stops
+            .entry(route.route.id.clone())
+            .or_default()
+            .extend(
+                route
+                    .trip
+                    .stop_times
+                    .iter()
+                    .map(|e| normalize_stop_id(&e.stop.id)),
+            );
+    }
+
+    let mut reached_stops = stops.clone();
+    let mut selected: HashSet<RouteId> = HashSet::new();
+    for _ in 0..=transfers {
+        let newly_selected = route_stops
+            .iter()
+            .filter(|(id, stops)| !selected.contains(*id) && !stops.is_disjoint(&reached_stops))
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        if newly_selected.is_empty() {
+            break;
+        }
+        for id in newly_selected {
+            reached_stops.extend(route_stops[&id].iter().cloned());
+            selected.insert(id);
+        }
+    }
+    selected
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use clap::ValueEnum;
+use gtfs_structures::{Route, Trip};
+use log::{debug, error, warn};
+
+use crate::GitRoute;
+
+/// What to do with the trips the tool cannot build
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnInvalid {
+    /// Leave them out and carry on
+    #[default]
+    Skip,
+    /// Stop before selecting the routes
+    Abort,
+}
+
+/// Problems found in the trips of the routes passing the filters
+#[derive(Debug, Default)]
+pub struct FeedReport {
+    /// Trips whose `route_id` is not in `routes.txt`
+    unknown_routes: BTreeSet<String>,
+    /// Trips without stop times
+    empty_trips: BTreeSet<String>,
+    single_stop_trips: BTreeSet<String>,
+    /// Stops without `stop_name`, named after their ID
+    unnamed_stops: BTreeSet<String>,
+}
+
+/// Number of IDs shown in each line of the report
+const EXAMPLES: usize = 5;
+
+impl FeedReport {
+    /// `known_routes` are all the routes of the feed, `routes` only the ones passing the filters
+    pub fn new(
+        known_routes: &HashSet<String>,
+        routes: &HashMap<String, Route>,
+        trips: &HashMap<String, Trip>,
+    ) -> FeedReport {
+        let mut report = FeedReport::default();
+        for trip in trips.values() {
+            if !known_routes.contains(&trip.route_id) {
+                report.unknown_routes.insert(trip.id.clone());
+                continue;
+            }
+            if !routes.contains_key(&trip.route_id) {
+                continue;
+            }
+            match trip.stop_times.len() {
+                0 => {
+                    report.empty_trips.insert(trip.id.clone());
+                }
+                1 => {
+                    report.single_stop_trips.insert(trip.id.clone());
+                }
+                _ => {}
+            }
+            for stop_time in &trip.stop_times {
+                if stop_time
+                    .stop
+                    .name
+                    .as_ref()
+                    .is_none_or(|e| e.trim().is_empty())
+                {
+                    report.unnamed_stops.insert(stop_time.stop.id.clone());
+                }
+            }
+        }
+        report
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unknown_routes.is_empty()
+            && self.empty_trips.is_empty()
+            && self.single_stop_trips.is_empty()
+            && self.unnamed_stops.is_empty()
+    }
+
+    /// Trips that cannot be built, left out with `OnInvalid::Skip`
+    pub fn invalid_trips(&self) -> impl Iterator<Item = &String> {
+        self.unknown_routes
+            .iter()
+            .chain(&self.empty_trips)
+            .chain(&self.single_stop_trips)
+    }
+
+    /// Warns about every kind of problem, with a few IDs. Every ID is logged at debug level.
+    pub fn log(&self) {
+        let sections = [
+            (&self.unknown_routes, "trips refer to an unknown route"),
+            (&self.empty_trips, "trips have no stop times"),
+            (&self.single_stop_trips, "trips have a single stop"),
+            (&self.unnamed_stops, "stops have no name, their ID is used"),
+        ];
+        for (ids, problem) in sections {
+            if ids.is_empty() {
+                continue;
+            }
+            let mut examples = ids.iter().take(EXAMPLES).cloned().collect::<Vec<_>>();
+            if ids.len() > EXAMPLES {
+                examples.push("…".to_string());
+            }
+            warn!("{} {}: {}", ids.len(), problem, examples.join(", "));
+            for id in ids {
+                debug!("{}: {}", problem, id);
+            }
+        }
+    }
+}
+
+/// Trimming to the area, skipping stops and the overrides can leave a route with less than two
+/// stops, which cannot be built. It is left out, or the run stops with `OnInvalid::Abort`.
+pub fn has_enough_stops(route: &GitRoute, on_invalid: OnInvalid) -> bool {
+    let count = route.stops().len();
+    if count >= 2 {
+        return true;
+    }
+    let problem = format!("{} only has {} stop(s) left", route.name, count);
+    if on_invalid == OnInvalid::Abort {
+        error!("{}", problem);
+        std::process::exit(1);
+    }
+    warn!("{}, it is left out", problem);
+    false
+}